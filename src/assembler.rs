@@ -0,0 +1,226 @@
+//! Assembler for the mnemonics of Cowgod's technical reference.
+//!
+//! Reads the listings the disassembler writes in `MnemonicStyle::Cowgod`, so a
+//! disassembly can be edited and assembled back into a ROM. The address and opcode
+//! columns of a listing are skipped, the opcode is always assembled from the
+//! mnemonic, and anything after a `;` is a comment. Numbers are hexadecimal with or
+//! without a `0x` prefix, as the disassembler writes them.
+//!
+//! ```text
+//! 0x200: (0x6005) LD V0, 0x5
+//! 0x202: (0x7001) ADD V0, 1
+//! 0x204: (0x1200) JP 0x200 ; loop
+//! DB 0xFF, 0x00
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+use instruction::Instruction;
+
+/// Error assembling a line of source
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+	/// Line number, starting from 1
+	pub line: usize,
+	/// The offending line
+	pub text: String
+}
+
+impl fmt::Display for AsmError
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(f, "Cannot assemble line {}: {}", self.line, self.text)
+	}
+}
+
+impl Error for AsmError {}
+
+/// Assemble $source into ROM bytes, loaded from 0x200 on.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError>
+{
+	let mut bytes = Vec::new();
+
+	for (n, line) in source.lines().enumerate()
+	{
+		let code = strip_columns(line.split(';').next().unwrap_or(""));
+		if code.is_empty() { continue; }
+
+		match assemble_line(code) {
+			Some(line_bytes) => bytes.extend_from_slice(&line_bytes),
+			None => return Err(AsmError { line: n + 1, text: line.trim().to_string() })
+		}
+	}
+
+	Ok(bytes)
+}
+
+/// Remove the address (`0x200:`) and opcode (`(0x6005)` or `60 05`) columns of a
+/// disassembly listing from $line.
+fn strip_columns(line: &str) -> &str
+{
+	let mut rest = line.trim();
+
+	if let Some(end) = rest.find(": ") {
+		if rest[..end].starts_with("0x") && !rest[..end].contains(' ') { rest = rest[end + 2..].trim_start(); }
+	}
+	if rest.starts_with("(0x") {
+		if let Some(end) = rest.find(')') { rest = rest[end + 1..].trim_start(); }
+	}
+	let tokens: Vec<&str> = rest.splitn(3, ' ').collect();
+	if tokens.len() == 3 && tokens[..2].iter().all(|t| t.len() == 2 && t.chars().all(|c| c.is_ascii_hexdigit())) {
+		rest = tokens[2].trim_start();
+	}
+
+	rest.trim_end()
+}
+
+/// Assemble a single instruction or `DB` directive into bytes.
+fn assemble_line(code: &str) -> Option<Vec<u8>>
+{
+	let (name, operands) = match code.find(' ') {
+		Some(end) => (&code[..end], code[end + 1..].trim()),
+		None => (code, "")
+	};
+	let name = name.to_ascii_uppercase();
+
+	// `DRW (V1, V2) for 5 bytes` is written by the disassembler, `DRW V1, V2, 5` is accepted too
+	let drw_operands = operands.replace(['(', ')'], "").replace(" for ", ", ").replace(" bytes", "");
+	let operands: Vec<String> = if name == "DRW" { split_operands(&drw_operands) } else { split_operands(operands) };
+	let ops: Vec<&str> = operands.iter().map(|op| op.as_str()).collect();
+
+	let word = |instruction: Instruction| Some(be_bytes(instruction.encode()));
+
+	match (name.as_str(), &ops[..]) {
+		("DB", bytes) if !bytes.is_empty() => bytes.iter().map(|b| number(b).filter(|&v| v <= 0xFF).map(|v| v as u8)).collect(),
+		("UNKNOWN", ["OPCODE:", op]) => number(op).map(be_bytes),
+		("CLS", []) => word(Instruction::Cls),
+		("RET", []) => word(Instruction::Ret),
+		("EXIT", []) => word(Instruction::Exit),
+		("SYS", [addr]) => word(Instruction::Sys(address(addr)?)),
+		("JP", ["V0", addr]) => word(Instruction::JpV0(address(addr)?)),
+		("JP", [addr]) => word(Instruction::Jp(address(addr)?)),
+		("CALL", [addr]) => word(Instruction::Call(address(addr)?)),
+		("SE", [x, y]) if register(y).is_some() => word(Instruction::SeReg(register(x)?, register(y)?)),
+		("SE", [x, kk]) => word(Instruction::Se(register(x)?, byte(kk)?)),
+		("SNE", [x, y]) if register(y).is_some() => word(Instruction::SneReg(register(x)?, register(y)?)),
+		("SNE", [x, kk]) => word(Instruction::Sne(register(x)?, byte(kk)?)),
+		("LD", ["I", "LONG", addr]) => {
+			let addr = number(addr)?;
+			let mut bytes = be_bytes(Instruction::LdILong.encode());
+			bytes.extend_from_slice(&be_bytes(addr));
+			Some(bytes)
+		},
+		("LD", ["I", addr]) => word(Instruction::Ldi(address(addr)?)),
+		("LD", ["DT", x]) => word(Instruction::LdVxIntoDt(register(x)?)),
+		("LD", ["ST", x]) => word(Instruction::LdVxIntoSt(register(x)?)),
+		("LD", ["F", x]) => word(Instruction::LdVxDigitIntoF(register(x)?)),
+		("LD", ["B", x]) => word(Instruction::LdVxIntoBcd(register(x)?)),
+		("LD", ["[I]", x]) => word(Instruction::LdV0ToVxIntoI(register(x)?)),
+		("LD", [x, "[I]"]) => word(Instruction::LdIIntoV0ToVx(register(x)?)),
+		("LD", [x, "DT"]) => word(Instruction::LdDtIntoVx(register(x)?)),
+		("LD", [x, "K"]) => word(Instruction::LdKIntoVx(register(x)?)),
+		("LD", [x, y]) if register(y).is_some() => word(Instruction::Ld(register(x)?, register(y)?)),
+		("LD", [x, kk]) => word(Instruction::Ldx(register(x)?, byte(kk)?)),
+		("ADD", ["I", x]) => word(Instruction::AddVx(register(x)?)),
+		("ADD", [x, y]) if register(y).is_some() => word(Instruction::AddReg(register(x)?, register(y)?)),
+		("ADD", [x, kk]) => word(Instruction::AddByte(register(x)?, byte(kk)?)),
+		("OR", [x, y]) => word(Instruction::Or(register(x)?, register(y)?)),
+		("AND", [x, y]) => word(Instruction::And(register(x)?, register(y)?)),
+		("XOR", [x, y]) => word(Instruction::Xor(register(x)?, register(y)?)),
+		("SUB", [x, y]) => word(Instruction::Sub(register(x)?, register(y)?)),
+		("SUBN", [x, y]) => word(Instruction::Subn(register(x)?, register(y)?)),
+		("SHR", [x]) => word(Instruction::Shr(register(x)?, 0)),
+		("SHR", [x, y]) => word(Instruction::Shr(register(x)?, register(y)?)),
+		("SHL", [x]) => word(Instruction::Shl(register(x)?, 0)),
+		("SHL", [x, y]) => word(Instruction::Shl(register(x)?, register(y)?)),
+		("RND", [x, kk]) => word(Instruction::Rnd(register(x)?, byte(kk)?)),
+		("DRW", [x, y, n]) => word(Instruction::Drw(register(x)?, register(y)?, number(n).filter(|&n| n <= 0xF)? as u8)),
+		("SKP", [x]) => word(Instruction::Skp(register(x)?)),
+		("SKNP", [x]) => word(Instruction::Sknp(register(x)?)),
+		("PLANE", [mask]) => word(Instruction::Plane(number(mask).filter(|&m| m <= 0xF)? as u8)),
+		_ => None
+	}
+}
+
+/// Split comma separated operands, upper casing them.
+fn split_operands(operands: &str) -> Vec<String>
+{
+	if operands.is_empty() { return Vec::new(); }
+	operands.split(',').flat_map(|op| op.split_whitespace()).map(|op| op.to_ascii_uppercase()).collect()
+}
+
+/// Parse a hexadecimal number with an optional `0x` prefix.
+fn number(s: &str) -> Option<u16>
+{
+	let digits = s.trim_start_matches("0X");
+	if digits.is_empty() { return None; }
+	u16::from_str_radix(digits, 16).ok()
+}
+
+/// Parse a 12-bit address.
+fn address(s: &str) -> Option<u16>
+{
+	number(s).filter(|&addr| addr <= 0xFFF)
+}
+
+/// Parse a byte.
+fn byte(s: &str) -> Option<u8>
+{
+	number(s).filter(|&b| b <= 0xFF).map(|b| b as u8)
+}
+
+/// Parse a register name, `V0` to `VF`.
+fn register(s: &str) -> Option<u8>
+{
+	if s.len() != 2 || !s.starts_with('V') { return None; }
+	u8::from_str_radix(&s[1..], 16).ok()
+}
+
+fn be_bytes(word: u16) -> Vec<u8>
+{
+	vec![(word >> 8) as u8, word as u8]
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[test]
+fn test_assemble()
+{
+	let source = "
+		CLS
+		LD V0, 0x5 ; comment
+		ADD V0, 1
+		drw v0, v1, 5
+		DRW (V0, V1) for F bytes
+		LD [I], VA
+		SHR V3
+		JP V0, 0x300
+		LD I, LONG 0xABCD
+		DB 0xFF, 0x01
+	";
+
+	assert!(assemble(source).unwrap() == vec![
+		0x00, 0xE0, 0x60, 0x05, 0x70, 0x01, 0xD0, 0x15, 0xD0, 0x1F, 0xFA, 0x55, 0x83, 0x06,
+		0xB3, 0x00, 0xF0, 0x00, 0xAB, 0xCD, 0xFF, 0x01
+	]);
+}
+
+#[test]
+fn test_assemble_listing()
+{
+	let listing = "0x200: (0x6005) LD V0, 0x5\n0x202: 12 00   JP 0x200\n0x204: (0xFFFF) Unknown opcode: 0xFFFF";
+	assert!(assemble(listing).unwrap() == vec![0x60, 0x05, 0x12, 0x00, 0xFF, 0xFF]);
+}
+
+#[test]
+fn test_assemble_errors()
+{
+	assert!(assemble("CLS\nLD V0, 0x100") == Err(AsmError { line: 2, text: "LD V0, 0x100".to_string() }));
+	assert!(assemble("JP 0x1000").is_err());
+	assert!(assemble("LD VG, 1").is_err());
+	assert!(assemble("FOO").is_err());
+}
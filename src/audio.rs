@@ -0,0 +1,203 @@
+//! Audio output of the CHIP-8 beeper.
+//!
+//! The CHIP-8 has a single tone that sounds while the sound timer is running.
+//! `Beeper` turns the state of the sound timer into square-wave samples and
+//! `WavWriter` stores samples as a 16-bit mono WAV file, for example to check beep
+//! timing against the sound timer.
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Sample rate of the generated audio in Hz.
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Pitch of the beep in Hz.
+pub const BEEP_HZ: u32 = 440;
+
+/// Amplitude of the square wave.
+const VOLUME: i16 = 8000;
+
+/// Size of the RIFF and fmt chunk headers written before the samples.
+const HEADER_LEN: u32 = 44;
+
+/// Square-wave generator for the beeper. Keeps its phase between calls so the wave
+/// stays continuous across frames.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Beeper {
+	/// Position within the current period, in samples times `BEEP_HZ`
+	phase: u32
+}
+
+impl Beeper
+{
+	/// Create a beeper at the start of a period.
+	pub fn new() -> Beeper
+	{
+		Beeper::default()
+	}
+
+	/// Append $count samples to $out: a square wave while $on, silence otherwise.
+	pub fn fill(&mut self, on: bool, count: usize, out: &mut Vec<i16>)
+	{
+		for _ in 0..count
+		{
+			if !on { out.push(0); continue; }
+
+			out.push(if self.phase < SAMPLE_RATE / 2 { VOLUME } else { -VOLUME });
+			self.phase = (self.phase + BEEP_HZ) % SAMPLE_RATE;
+		}
+	}
+}
+
+/// Decides frame by frame whether the beeper sounds, stretching beeps that are
+/// shorter than a minimum number of frames.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BeepSustain {
+	/// Minimum length of a beep in frames
+	min_frames: u32,
+	/// Frames left of the minimum length of the current beep
+	remaining: u32,
+	/// Whether the sound timer was running in the previous frame
+	timer_was_on: bool
+}
+
+impl BeepSustain
+{
+	/// Sustain every beep for at least $min_frames frames. 0 sustains nothing.
+	pub fn new(min_frames: u32) -> BeepSustain
+	{
+		BeepSustain { min_frames, ..BeepSustain::default() }
+	}
+
+	/// Advance by a frame in which the sound timer was running if $timer_on, and
+	/// return whether the beeper sounds during it.
+	pub fn update(&mut self, timer_on: bool) -> bool
+	{
+		if timer_on && !self.timer_was_on { self.remaining = self.min_frames; }
+		self.timer_was_on = timer_on;
+
+		let sounding = timer_on || self.remaining > 0;
+		self.remaining = self.remaining.saturating_sub(1);
+		sounding
+	}
+}
+
+/// Writes 16-bit mono samples at `SAMPLE_RATE` as a WAV file. The header is updated
+/// after every write, so the output is a complete file at any time.
+pub struct WavWriter<W: Write + Seek> {
+	out: W,
+	/// Number of samples written so far
+	samples: u32
+}
+
+impl<W: Write + Seek> WavWriter<W>
+{
+	/// Start a WAV file on $out, which should be empty.
+	pub fn new(out: W) -> io::Result<WavWriter<W>>
+	{
+		let mut writer = WavWriter { out, samples: 0 };
+		writer.write_header()?;
+		Ok(writer)
+	}
+
+	/// Append $samples to the file.
+	pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()>
+	{
+		let bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+		self.out.seek(SeekFrom::Start((HEADER_LEN + 2 * self.samples) as u64))?;
+		self.out.write_all(&bytes)?;
+		self.samples += samples.len() as u32;
+		self.write_header()
+	}
+
+	/// Number of samples written so far.
+	pub fn samples(&self) -> u32
+	{
+		self.samples
+	}
+
+	/// Finish the file and return the underlying writer.
+	pub fn into_inner(self) -> W
+	{
+		self.out
+	}
+
+	fn write_header(&mut self) -> io::Result<()>
+	{
+		let data_len = 2 * self.samples;
+
+		let mut header = Vec::with_capacity(HEADER_LEN as usize);
+		header.extend_from_slice(b"RIFF");
+		header.extend_from_slice(&(HEADER_LEN - 8 + data_len).to_le_bytes());
+		header.extend_from_slice(b"WAVEfmt ");
+		header.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk length
+		header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+		header.extend_from_slice(&1u16.to_le_bytes()); // Mono
+		header.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+		header.extend_from_slice(&(2 * SAMPLE_RATE).to_le_bytes()); // Bytes per second
+		header.extend_from_slice(&2u16.to_le_bytes()); // Bytes per sample
+		header.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+		header.extend_from_slice(b"data");
+		header.extend_from_slice(&data_len.to_le_bytes());
+
+		self.out.seek(SeekFrom::Start(0))?;
+		self.out.write_all(&header)?;
+		self.out.flush()
+	}
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[cfg(test)]
+use std::io::Cursor;
+
+#[test]
+fn test_beeper()
+{
+	let mut beeper = Beeper::new();
+	let mut samples = Vec::new();
+
+	beeper.fill(false, 10, &mut samples);
+	assert!(samples.iter().all(|&s| s == 0));
+
+	beeper.fill(true, SAMPLE_RATE as usize, &mut samples);
+	let wave = &samples[10..];
+	let rising = wave.windows(2).filter(|pair| pair[0] < 0 && pair[1] > 0).count();
+	assert!(rising == BEEP_HZ as usize - 1); // One period per cycle of the tone
+	assert!(wave.iter().all(|&s| s == VOLUME || s == -VOLUME));
+}
+
+#[test]
+fn test_beep_sustain()
+{
+	// A single frame of sound timer is stretched to 3 frames
+	let mut sustain = BeepSustain::new(3);
+	let frames: Vec<bool> = [true, false, false, false, false].iter().map(|&on| sustain.update(on)).collect();
+	assert!(frames == vec![true, true, true, false, false]);
+
+	// Longer beeps are unchanged, and a new beep starts a new minimum
+	let frames: Vec<bool> = [true, true, true, true, false, true, false].iter().map(|&on| sustain.update(on)).collect();
+	assert!(frames == vec![true, true, true, true, false, true, true]);
+
+	let mut exact = BeepSustain::new(0);
+	assert!(exact.update(true) && !exact.update(false));
+}
+
+#[test]
+fn test_wav_writer()
+{
+	let mut writer = WavWriter::new(Cursor::new(Vec::new())).unwrap();
+	writer.write_samples(&[1, -1]).unwrap();
+	writer.write_samples(&[0x1234]).unwrap();
+	assert!(writer.samples() == 3);
+
+	let bytes = writer.into_inner().into_inner();
+	assert!(bytes.len() == 44 + 6);
+	assert!(&bytes[0..4] == b"RIFF" && &bytes[8..16] == b"WAVEfmt ");
+	assert!(bytes[4..8] == [42, 0, 0, 0]);
+	assert!(bytes[24..28] == SAMPLE_RATE.to_le_bytes());
+	assert!(&bytes[36..40] == b"data");
+	assert!(bytes[40..44] == [6, 0, 0, 0]);
+	assert!(bytes[44..] == [0x01, 0x00, 0xFF, 0xFF, 0x34, 0x12]);
+}
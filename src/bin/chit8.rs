@@ -2,8 +2,10 @@
 
 extern crate chip8;
 
-use chip8::rom::Rom;
+use chip8::rom::{self, Rom};
+use chip8::config::EmulatorConfig;
 use std::fs::File;
+use std::process;
 use std::path::PathBuf;
 use std::env;
 
@@ -11,27 +13,37 @@ const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
 struct Options<>
 {
-	rom_path: PathBuf
+	rom_path: PathBuf,
+	verbose: bool,
+	logo: bool,
+	byteswap: bool
 }
 
 fn usage()
 {
 	println!("CHIT8 emulator / disassembler {}", VERSION);
 	println!("=====================================");
-	println!("Usage: chit8 <path-to-rom>");
+	println!("Usage: chit8 [-v|--verbose] [--byteswap] <path-to-rom>");
+	println!("       chit8 [-v|--verbose] --logo");
+	println!("  -v, --verbose  Print the active platform and quirks before running");
+	println!("  --logo         Run the built-in logo ROM instead of a file");
+	println!("  --byteswap     Swap the bytes of every opcode, for byte-swapped ROM files");
 }
 
 fn parse_cmdline_args() -> Option<Options>
 {
-	let mut opts = Options { rom_path: PathBuf::new() };
+	let mut opts = Options { rom_path: PathBuf::new(), verbose: false, logo: false, byteswap: false };
 
 	for arg in env::args().skip(1) {
 		match &*arg {
+			"-v" | "--verbose" => { opts.verbose = true }
+			"--logo" => { opts.logo = true }
+			"--byteswap" => { opts.byteswap = true }
 			_ => { opts.rom_path = PathBuf::from(arg) }
 		} 
 	}
 
-	if !(opts.rom_path.is_file()) {
+	if !opts.logo && !(opts.rom_path.is_file()) {
 		usage();
 		return None;
 	}
@@ -39,13 +51,29 @@ fn parse_cmdline_args() -> Option<Options>
 	return Some(opts);
 }
 
+/// Loads the ROM at $path, printing the reason and returning None if that fails.
+fn load_rom(path: &PathBuf) -> Option<Rom>
+{
+	let mut file = match File::open(path) { Ok(x) => x, Err(err) =>  { println!("ROM Open error: {}", err.to_string()); return None; }};
+
+	match Rom::new(&mut file, path.file_name().unwrap_or_default().to_str().unwrap_or_default().to_owned()).and_then(Rom::non_empty) { Ok(rom) => Some(rom), Err(err) => { println!("ROM loading error: {}", err.to_string()); None }}
+}
+
 /// Loads the provided ROM and calls the library for disassembly.
 pub fn main() {
 	let opts = match parse_cmdline_args() { Some(opts) => opts, None => { return; } };
-	let mut file = match File::open(&opts.rom_path) { Ok(x) => x, Err(err) =>  { println!("ROM Open error: {}", err.to_string()); return; }};
+	let rom = if opts.logo { rom::logo_rom() } else { match load_rom(&opts.rom_path) { Some(rom) => rom, None => { return; } } };
 
-	let rom = match Rom::new(&mut file, opts.rom_path.file_name().unwrap_or_default().to_str().unwrap_or_default().to_owned()) { Ok(rom) => rom, Err(err) => { println!("ROM loading error: {}", err.to_string()); return; }};
+	let rom = if opts.byteswap { rom.byteswapped() } else { rom };
+	if !opts.byteswap && rom::looks_byteswapped(&rom) {
+		println!("Warning: the ROM looks byte-swapped, try running it with --byteswap");
+	}
 
 	println!("ROM loaded: {}", rom);
-    chip8::emulate(rom);
+	let config = EmulatorConfig::default();
+	if opts.verbose { println!("Quirks: {}", config.quirks.describe()); }
+	if let Err(err) = chip8::emulate(rom, config) {
+		println!("Emulation error: {}", err);
+		process::exit(1);
+	}
 }
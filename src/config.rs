@@ -0,0 +1,187 @@
+//! Configuration for the CHIP-8 emulation.
+//!
+//! CHIP-8 interpreters differ in how some opcodes behave. `QuirkConfig` selects
+//! which of these behaviors the emulated CPU follows. `EmulatorConfig` holds the
+//! settings for a complete emulation run.
+
+use cpu::DEFAULT_TIMER_HZ;
+use display::Resolution;
+
+/// CHIP-8 variants the emulation can follow.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Platform {
+	/// The original CHIP-8 interpreter
+	#[default]
+	Chip8,
+	/// CHIP-48, the HP-48 interpreter SuperCHIP grew out of. Has the opcodes of CHIP-8
+	/// but shifts Vx in place and leaves I unchanged on register loads and stores.
+	Chip48,
+	/// SuperCHIP, which adds opcodes such as `EXIT`
+	SuperChip,
+	/// ETI-660, which has a taller 64x48 display
+	Eti660,
+	/// XO-CHIP, which extends SuperCHIP with color planes and more memory
+	XoChip
+}
+
+impl Platform
+{
+	/// Display resolution the platform starts in.
+	pub fn resolution(&self) -> Resolution
+	{
+		match *self {
+			Platform::Eti660 => Resolution::Eti660,
+			_ => Resolution::Low
+		}
+	}
+}
+
+/// What happens when the program counter runs past the end of memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PcOverflow {
+	/// Continue at address 0x000, which usually runs into the font data
+	#[default]
+	Wrap,
+	/// Halt the CPU as if `EXIT` had been executed
+	Halt,
+	/// Stop with `CpuError::ProgramCounterOverflow`
+	Error
+}
+
+/// Behavioral quirks of the emulated CPU. The defaults match the behavior
+/// most CHIP-8 programs expect.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QuirkConfig {
+	/// Treat `SYS addr` (0NNN) as an error instead of ignoring it. Useful for
+	/// catching programs that run into data during development.
+	pub sys_is_error: bool,
+
+	/// Raise an error when the program counter goes below 0x200 into the font and
+	/// interpreter area, which usually means a jump went astray.
+	pub guard_reserved_execution: bool,
+
+	/// Allow only one `DRW` per frame, as the COSMAC VIP waited for the vertical blank
+	/// before drawing. Further draws are retried on the next frame.
+	pub display_wait: bool,
+
+	/// Reset VF to 0 after `OR`, `AND` and `XOR` (8xy1, 8xy2, 8xy3), as the COSMAC VIP did.
+	pub logic_resets_vf: bool,
+
+	/// Shift Vy into Vx with `SHR` and `SHL` (8xy6, 8xyE), as the COSMAC VIP did, instead
+	/// of shifting Vx in place.
+	pub shift_uses_vy: bool,
+
+	/// Advance I past the last register with `LD [I], Vx` and `LD Vx, [I]` (Fx55, Fx65),
+	/// as the COSMAC VIP did.
+	pub load_store_increments_i: bool,
+
+	/// Behavior when an opcode would be fetched from past the end of memory, including
+	/// an opcode straddling the end. With 64 KB XO-CHIP memory the program counter
+	/// always wraps at 16 bits.
+	pub pc_overflow: PcOverflow,
+
+	/// Platform whose opcodes are available. Opcodes of other platforms are treated as unknown.
+	pub platform: Platform
+}
+
+impl QuirkConfig
+{
+	/// One line summary of the platform and every quirk, such as
+	/// `platform=Chip8, sys_is_error=false, ...`. Printed before running a ROM to help
+	/// diagnose programs that misbehave because of the selected quirks.
+	pub fn describe(&self) -> String
+	{
+		format!("platform={:?}, sys_is_error={}, guard_reserved_execution={}, display_wait={}, logic_resets_vf={}, shift_uses_vy={}, load_store_increments_i={}, pc_overflow={:?}",
+			self.platform, self.sys_is_error, self.guard_reserved_execution, self.display_wait,
+			self.logic_resets_vf, self.shift_uses_vy, self.load_store_increments_i, self.pc_overflow)
+	}
+
+	/// Quirks matching the original interpreter of $platform, so a platform can be
+	/// selected by name instead of toggling each quirk. Quirks that only help during
+	/// development, such as `sys_is_error`, are left off.
+	pub fn for_platform(platform: Platform) -> QuirkConfig
+	{
+		let vip = match platform {
+			Platform::Chip8 | Platform::Eti660 => true,
+			Platform::Chip48 | Platform::SuperChip | Platform::XoChip => false
+		};
+		let xo = platform == Platform::XoChip;
+
+		QuirkConfig {
+			display_wait: vip,
+			logic_resets_vf: vip,
+			shift_uses_vy: vip || xo,
+			load_store_increments_i: vip || xo,
+			platform,
+			..QuirkConfig::default()
+		}
+	}
+}
+
+/// Settings for running the emulation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EmulatorConfig {
+	/// Behavioral quirks of the emulated CPU
+	pub quirks: QuirkConfig,
+	/// Cycles executed per frame. Each opcode costs one cycle unless `weighted_timing`
+	/// is set, in which case a value around 3668 matches the COSMAC VIP.
+	pub cycles_per_frame: u32,
+	/// Frames per second. The timers count down once per frame.
+	pub timer_hz: u32,
+	/// Report suspicious but legal behavior, such as wrapping arithmetic, as trace warnings
+	pub strict: bool,
+	/// Report stores to addresses of recently executed opcodes (self-modifying code) as
+	/// trace warnings
+	pub detect_smc: bool,
+	/// Stop with an error after this many executed opcodes. Keeps automated runs from hanging.
+	pub max_instructions: Option<u64>,
+	/// Charge each opcode its approximate COSMAC VIP cost instead of a single cycle
+	pub weighted_timing: bool,
+	/// Blank the display with `Display::shutdown` when the program exits, instead of
+	/// leaving the last frame on screen
+	pub blank_on_exit: bool,
+	/// Keep the beeper sounding for at least this many frames once the sound timer
+	/// starts, so very short beeps don't turn into clicks. 0 follows the timer exactly.
+	pub min_beep_frames: u32,
+	/// Factor `cycles_per_frame` is multiplied by while the turbo key is held, see
+	/// `Input::turbo_held`. The timers keep ticking once per frame.
+	pub turbo_factor: u32
+}
+
+impl Default for EmulatorConfig
+{
+	fn default() -> EmulatorConfig
+	{
+		EmulatorConfig { quirks: QuirkConfig::default(), cycles_per_frame: 10, timer_hz: DEFAULT_TIMER_HZ, strict: false, detect_smc: false, max_instructions: None, weighted_timing: false, blank_on_exit: false, min_beep_frames: 0, turbo_factor: 4 }
+	}
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[test]
+fn test_describe()
+{
+	assert!(QuirkConfig::for_platform(Platform::Chip48).describe() ==
+		"platform=Chip48, sys_is_error=false, guard_reserved_execution=false, display_wait=false, logic_resets_vf=false, shift_uses_vy=false, load_store_increments_i=false, pc_overflow=Wrap");
+
+	let quirks = QuirkConfig { sys_is_error: true, shift_uses_vy: true, ..QuirkConfig::default() };
+	assert!(quirks.describe().contains("sys_is_error=true, guard_reserved_execution=false"));
+}
+
+#[test]
+fn test_chip48_preset()
+{
+	let chip48 = QuirkConfig::for_platform(Platform::Chip48);
+	assert!(chip48.platform == Platform::Chip48);
+	assert!(!chip48.shift_uses_vy);
+	assert!(!chip48.load_store_increments_i);
+	assert!(!chip48.logic_resets_vf && !chip48.display_wait);
+	assert!(!chip48.sys_is_error && !chip48.guard_reserved_execution);
+
+	let chip8 = QuirkConfig::for_platform(Platform::Chip8);
+	assert!(chip8.shift_uses_vy && chip8.load_store_increments_i && chip8.logic_resets_vf && chip8.display_wait);
+
+	assert!(Platform::Chip48.resolution() == Resolution::Low);
+}
@@ -1,12 +1,18 @@
 //! Disassembly module for CHIT8 emulator and disassembler.
 //!
-//! Contains `decode_opcode!` macro to decode opcode and the parameters for use in the emulated CPU
-//! and the disassembler. The disassembler contains definitions for all these opcodes that provide a 
-//! string representation of the opcode and it's parameters. 
+//! Contains `decode_opcode!` macro to decode opcode and the parameters for use in
+//! `instruction::decode`, whose result the emulated CPU runs with `Cpu::execute`.
+//! The disassembler decodes each opcode into an `Instruction` and writes it out in
+//! the selected `MnemonicStyle`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 
 use ram::Memory;
+use instruction::{self, Instruction, MnemonicStyle};
+use rom::Rom;
 
-/// Macro to decode opcode and call the corresponsing function on the emulated CPU or disassembler
+/// Macro to decode opcode and call the corresponsing function on the emulated CPU or instruction decoder
 /// with the correct parameters parsed from the opcode.
 ///
 /// Source for the opcodes: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
@@ -15,12 +21,14 @@ macro_rules! decode_opcode {
 		match $op {
 			0x00E0 => { $this.cls() },
 			0x00EE => { $this.ret() },
+			0x00FD => { $this.exit() },
+			0xF000 => { $this.ld_i_long() },
 			op @ 0x0000 ... 0x0FFF => { $this.sys(op & 0xFFF) },
 			op @ 0x1000 ... 0x1FFF => { $this.jp(op & 0x0FFF) },
 			op @ 0x2000 ... 0x2FFF => { $this.call(op & 0x0FFF) },
 			op @ 0x3000 ... 0x3FFF => { $this.se(((op & 0x0F00) >> 8) as u8, (op & 0x00FF) as u8) },
 			op @ 0x4000 ... 0x4FFF => { $this.sne(((op & 0x0F00) >> 8) as u8, (op & 0x00FF) as u8) },
-			op @ 0x5000 ... 0x5FFF => { $this.se_reg(((op & 0x0F00) >> 8) as u8, ((op & 0x00F0) >> 4) as u8) },
+			op @ 0x5000 ... 0x5FFF if (op & 0x000F) == 0x0 => { $this.se_reg(((op & 0x0F00) >> 8) as u8, ((op & 0x00F0) >> 4) as u8) },
 			op @ 0x6000 ... 0x6FFF => { $this.ldx(((op & 0x0F00) >> 8) as u8, (op & 0x00FF) as u8) },
 			op @ 0x7000 ... 0x7FFF => { $this.add_byte(((op & 0x0F00) >> 8) as u8, (op & 0x00FF) as u8) },
 			op @ 0x8000 ... 0x8FFF if (op & 0x000F) == 0x0 => { $this.ld(((op & 0x0F00) >> 8) as u8, ((op & 0x00F0) >> 4) as u8) },
@@ -29,9 +37,9 @@ macro_rules! decode_opcode {
 			op @ 0x8000 ... 0x8FFF if (op & 0x000F) == 0x3 => { $this.xor(((op & 0x0F00) >> 8) as u8, ((op & 0x00F0) >> 4) as u8) },
 			op @ 0x8000 ... 0x8FFF if (op & 0x000F) == 0x4 => { $this.add_reg(((op & 0x0F00) >> 8) as u8, ((op & 0x00F0) >> 4) as u8) },
 			op @ 0x8000 ... 0x8FFF if (op & 0x000F) == 0x5 => { $this.sub(((op & 0x0F00) >> 8) as u8, ((op & 0x00F0) >> 4) as u8) },
-			op @ 0x8000 ... 0x8FFF if (op & 0x000F) == 0x6 => { $this.shr(((op & 0x0F00) >> 8) as u8) },
+			op @ 0x8000 ... 0x8FFF if (op & 0x000F) == 0x6 => { $this.shr(((op & 0x0F00) >> 8) as u8, ((op & 0x00F0) >> 4) as u8) },
 			op @ 0x8000 ... 0x8FFF if (op & 0x000F) == 0x7 => { $this.subn(((op & 0x0F00) >> 8) as u8, ((op & 0x00F0) >> 4) as u8) },
-			op @ 0x8000 ... 0x8FFF if (op & 0x000F) == 0xE => { $this.shl(((op & 0x0F00) >> 8) as u8) },
+			op @ 0x8000 ... 0x8FFF if (op & 0x000F) == 0xE => { $this.shl(((op & 0x0F00) >> 8) as u8, ((op & 0x00F0) >> 4) as u8) },
 			op @ 0x9000 ... 0x9FFF if (op & 0x000F) == 0x0 => { $this.sne_reg(((op & 0x0F00) >> 8) as u8, ((op & 0x00F0) >> 4) as u8) },
 			op @ 0xA000 ... 0xAFFF => { $this.ldi(op & 0x0FFF)},
 			op @ 0xB000 ... 0xBFFF => { $this.jp_v0(op & 0x0FFF)},
@@ -39,6 +47,7 @@ macro_rules! decode_opcode {
 			op @ 0xD000 ... 0xDFFF => { $this.drw(((op & 0x0F00) >> 8) as u8, ((op & 0x00F0) >> 4) as u8, (op & 0x000F) as u8) },
 			op @ 0xE000 ... 0xEFFF if (op & 0x00FF) == 0x9E => { $this.skp(((op & 0x0F00) >> 8) as u8) },
 			op @ 0xE000 ... 0xEFFF if (op & 0x00FF) == 0xA1 => { $this.sknp(((op & 0x0F00) >> 8) as u8) },
+			op if (op & 0xF0FF) == 0xF001 => { $this.plane(((op & 0x0F00) >> 8) as u8) },
 			op @ 0xF000 ... 0xFFFF if (op & 0x00FF) == 0x07 => { $this.ld_dt_into_vx(((op & 0x0F00) >> 8) as u8) },
 			op @ 0xF000 ... 0xFFFF if (op & 0x00FF) == 0x0A => { $this.ld_k_into_vx(((op & 0x0F00) >> 8) as u8) },
 			op @ 0xF000 ... 0xFFFF if (op & 0x00FF) == 0x15 => { $this.ld_vx_into_dt(((op & 0x0F00) >> 8) as u8) },
@@ -53,8 +62,7 @@ macro_rules! decode_opcode {
 	}
 }
 
-/// Disassembler for the CHIP-8. Comments for the emulated opcodes are
-/// sourced from http://devernay.free.fr/hacks/chip8/C8TECH10.HTM and modified.
+/// Disassembler for the CHIP-8.
 pub struct Disassembler<'a>
 {
 	/// Current program counter. Initialized to 0x200.
@@ -68,254 +76,513 @@ impl<'a> Disassembler<'a> {
 	/// the program counter for the opcode in a tuple.
 	fn next_opcode(&mut self) -> (u16, u16)
 	{
-		let hi = (self.ram.lb(self.pc) as u16) << 8;
-		let low = self.ram.lb(self.pc+1) as u16;
 		let pc = self.pc;
 		self.pc = self.pc + 2;
-		(pc, low | hi)
+		(pc, self.ram.lw(pc))
 	}
 
-	/// Clear the display.
-	fn cls(&mut self) -> String
-	{
-		"CLS".to_string()
+	/// Run the disassembly and print the results.
+	/// Runs until program counter reaches the end of the ROM.
+	pub fn disasm(&mut self, rom_length: u16) {
+		self.disasm_with(rom_length, DisasmOptions::default());
 	}
 
-	/// Return from a subroutine.
-	/// The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
-	fn ret(&mut self) -> String
-	{
-		"RET".to_string()
-	}
+	/// Run the disassembly with the given options and print the results.
+	/// Runs until program counter reaches the end of the ROM.
+	pub fn disasm_with(&mut self, rom_length: u16, options: DisasmOptions) {
+		println!("");
+		println!("===");
 
-	/// Jump to a machine code routine at addr.
-	/// Commonly ignored.
-	fn sys(&mut self, addr: u16) -> String
-	{
-		format!("SYS 0x{:0>4X}", addr)
+		for line in self.disasm_lines(rom_length, options) {
+			println!("{}", line);
+		}
 	}
 
-	/// Jump to location addr.
-	fn jp(&mut self, addr: u16) -> String
-	{
-		format!("JP {:#X}", addr)
+	/// Run the disassembly and return the formatted lines.
+	/// Runs until program counter reaches the end of the ROM.
+	pub fn disasm_lines(&mut self, rom_length: u16, options: DisasmOptions) -> Vec<String> {
+		let opcodes = self.disasm_opcodes(rom_length, options);
+		let xrefs = if options.show_xrefs { collect_xrefs(&opcodes) } else { HashMap::new() };
+		let digits = if options.show_font_digits { collect_font_digits(&opcodes) } else { HashMap::new() };
+
+		opcodes.iter()
+			.map(|&(pc, op, ref mnemonic)| {
+				let mut line = format_line(pc, op, mnemonic, options);
+				let others: Vec<String> = instruction::decode(op).target()
+					.and_then(|target| xrefs.get(&target))
+					.map(|sources| sources.iter().filter(|&&source| source != pc).map(|source| format!("{:#X}", source)).collect())
+					.unwrap_or_default();
+				if !others.is_empty() { line.push_str(&format!(" ; xrefs: {}", others.join(", "))); }
+				if let Some(digit) = digits.get(&pc) { line.push_str(&format!(" ; font digit {:X} @ 0x{:02X}", digit, digit * 5)); }
+				line
+			})
+			.collect()
+	}
+
+	/// Run the disassembly and return it as a JSON array with an
+	/// `{ "addr": .., "opcode": .., "mnemonic": .. }` object per opcode.
+	/// Runs until program counter reaches the end of the ROM.
+	pub fn disasm_json(&mut self, rom_length: u16) -> String {
+		let objects: Vec<String> = self.disasm_opcodes(rom_length, DisasmOptions::default()).iter()
+			.map(|&(pc, op, ref mnemonic)| format!("{{\"addr\":{},\"opcode\":{},\"mnemonic\":\"{}\"}}", pc, op, json_escape(mnemonic)))
+			.collect();
+		format!("[{}]", objects.join(","))
 	}
 
-	/// Call subroutine at addr.
-	/// The interpreter increments the stack pointer, then puts the current PC on the top of the stack. The PC is then set to addr.
-	fn call(&mut self, addr: u16) -> String
-	{
-		format!("CALL {:#X}", addr)
-	}
+	/// Decode every opcode until the end of the ROM into (address, opcode, mnemonic).
+	fn disasm_opcodes(&mut self, rom_length: u16, options: DisasmOptions) -> Vec<(u16, u16, String)> {
+		let mut opcodes = Vec::new();
 
-	/// Skip next instruction if Vreg == val.
-	fn se(&mut self, reg: u8, val: u8) -> String 
-	{
-		format!("SE V{:X}, {:X}", reg, val)
-	}
+		while self.pc < 0x200 + rom_length {
+			let op = self.next_opcode();
+			let instruction = instruction::decode(op.1);
+			if let Instruction::Unknown(_) = instruction {
+				if options.stop_on_unknown { break; }
+			}
+			let mut mnemonic = match instruction {
+				Instruction::Unknown(op) if options.skip_unknown_as_data && options.style == MnemonicStyle::Cowgod =>
+					format!("DB 0x{:02X}, 0x{:02X}", op >> 8, op & 0xFF),
+				_ => instruction.mnemonic(options.style)
+			};
+			if instruction == Instruction::LdILong {
+				// The address follows the opcode
+				let addr = self.next_opcode().1;
+				mnemonic.push_str(&format!(" {:#X}", addr));
+			}
+			opcodes.push((op.0, op.1, mnemonic));
+		}
 
-	/// Skip next instruction if Vreg != val.
-	fn sne(&mut self, reg: u8, val: u8) -> String 
-	{
-		format!("SNE V{:X}, {:X}", reg, val)
+		opcodes
 	}
+}
 
-	/// Skip next instruction if Vreg1 == Vreg2.
-	fn se_reg(&mut self, reg1: u8, reg2: u8) -> String 
-	{
-		format!("SE V{:X}, V{:X}", reg1, reg2)
+/// Map every `JP`/`CALL` target in $opcodes to the addresses of the opcodes referencing it.
+fn collect_xrefs(opcodes: &[(u16, u16, String)]) -> HashMap<u16, Vec<u16>>
+{
+	let mut xrefs: HashMap<u16, Vec<u16>> = HashMap::new();
+	for &(pc, op, _) in opcodes {
+		if let Some(target) = instruction::decode(op).target() {
+			xrefs.entry(target).or_default().push(pc);
+		}
 	}
+	xrefs
+}
 
-	/// Set Vreg = val.
-	fn ldx(&mut self, reg: u8, val: u8) -> String
-	{
-		format!("LD V{:X}, {:#X}", reg, val)
+/// Map every `LD F, Vx` in $opcodes whose Vx holds a known digit to that digit.
+///
+/// Register values are tracked through `LD Vx, byte` and `ADD Vx, byte` in a single
+/// linear pass. Anything else writing a register makes it unknown, as do jump
+/// targets and calls, where other code may have changed the registers. An
+/// instruction following a skip may not run, so it also makes its registers unknown.
+fn collect_font_digits(opcodes: &[(u16, u16, String)]) -> HashMap<u16, u8>
+{
+	let targets = collect_xrefs(opcodes);
+	let mut known: [Option<u8>;16] = [None;16];
+	let mut conditional = false;
+	let mut digits = HashMap::new();
+
+	for &(pc, op, _) in opcodes {
+		if targets.contains_key(&pc) { known = [None;16]; }
+
+		let instruction = instruction::decode(op);
+		match instruction {
+			Instruction::LdVxDigitIntoF(x) => {
+				if let Some(value) = known[x as usize] {
+					if value <= 0xF { digits.insert(pc, value); }
+				}
+			},
+			Instruction::Ldx(x, byte) if !conditional => known[x as usize] = Some(byte),
+			Instruction::AddByte(x, byte) if !conditional => known[x as usize] = known[x as usize].map(|value| value.wrapping_add(byte)),
+			Instruction::Call(_) => known = [None;16],
+			_ => {
+				let written = instruction.registers_written();
+				for (reg, value) in known.iter_mut().enumerate() {
+					if written & (1 << reg) != 0 { *value = None; }
+				}
+			}
+		}
+		conditional = instruction.is_branch();
 	}
+	digits
+}
 
-	/// Set Vreg = Vreg + byte.
-	fn add_byte(&mut self, reg: u8, byte: u8) -> String
-	{
-		format!("ADD V{:X}, {:X}", reg, byte)
+/// Escape $s for use inside a JSON string.
+fn json_escape(s: &str) -> String
+{
+	let mut escaped = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'"' => escaped.push_str("\\\""),
+			'\\' => escaped.push_str("\\\\"),
+			c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+			c => escaped.push(c)
+		}
 	}
+	escaped
+}
 
-	/// Set Vreg1 = Vreg2.
-	fn ld(&mut self, reg1: u8, reg2: u8) -> String
-	{
-		format!("LD V{:X}, V{:X}", reg1, reg2)
-	}
+/// Output options for the disassembler.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisasmOptions {
+	/// Show the raw opcode bytes in a column (`12 28`) instead of the opcode word (`(0x1228)`)
+	pub show_bytes: bool,
+	/// Show the address of each opcode at the start of the line
+	pub show_addresses: bool,
+	/// Assembly dialect of the mnemonics
+	pub style: MnemonicStyle,
+	/// Annotate `JP`/`CALL` lines with the other addresses referencing the same target
+	pub show_xrefs: bool,
+	/// End the disassembly at the first word that does not decode, for ROMs where
+	/// the code is known to end there
+	pub stop_on_unknown: bool,
+	/// Write words that do not decode as `DB` data bytes instead of `Unknown opcode`.
+	/// Octo style always writes them as plain bytes.
+	pub skip_unknown_as_data: bool,
+	/// Annotate `LD F, Vx` lines with the font digit I ends up pointing at, when the
+	/// value of Vx is known from the preceding code
+	pub show_font_digits: bool
+}
 
-	/// Set Vreg1 = Vreg1 || Vreg2.
-	fn or(&mut self, reg1: u8, reg2: u8) -> String
-	{
-		format!("OR V{:X}, V{:X}", reg1, reg2)
-	}
+impl Default for DisasmOptions
+{
+	fn default() -> DisasmOptions { DisasmOptions { show_bytes: false, show_addresses: true, style: MnemonicStyle::Cowgod, show_xrefs: false, stop_on_unknown: false, skip_unknown_as_data: false, show_font_digits: false } }
+}
 
-	/// Set Vreg1 = Vreg1 && Vreg2.
-	fn and(&mut self, reg1: u8, reg2: u8) -> String
-	{
-		format!("AND V{:X}, V{:X}", reg1, reg2)
-	}
+/// Format a single line of disassembly for the opcode at $pc.
+pub fn format_line(pc: u16, op: u16, mnemonic: &str, options: DisasmOptions) -> String
+{
+	let mut line = String::new();
+	if options.show_addresses { line.push_str(&format!("{:#X}: ", pc)); }
+	if options.show_bytes {
+		line.push_str(&format!("{:0>2X} {:0>2X}   ", op >> 8, op & 0xFF));
+	} else {
+		line.push_str(&format!("(0x{:0>4X}) ", op));
+	}
+	line.push_str(mnemonic);
+	line
+}
 
-	/// Set Vreg1 = Vreg1 ^ Vreg2.
-	fn xor(&mut self, reg1: u8, reg2: u8) -> String
-	{
-		format!("XOR V{:X}, V{:X}", reg1, reg2)
-	}
+/// A line of `disasm_smart` output: an instruction or a run of data bytes, each with
+/// the address it is loaded at.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Line {
+	Code(u16, Instruction),
+	Data(u16, Vec<u8>)
+}
 
-	/// Set Vreg1 = Vreg1 + Vreg2, set VF = carry.
-	/// The values of Vreg1 and Vreg2 are added together. If the result is greater than 8 bits, VF is set to 1, otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vreg1.
-	fn add_reg(&mut self, reg1: u8, reg2: u8) -> String
-	{
-		format!("ADD V{:X}, V{:X}", reg1, reg2)
+impl fmt::Display for Line
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			Line::Code(addr, instruction) => write!(f, "{:#X}: {}", addr, instruction),
+			Line::Data(addr, ref bytes) => {
+				let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+				write!(f, "{:#X}: DB {}", addr, bytes.join(", "))
+			}
+		}
 	}
+}
 
-	/// Set Vreg1 = Vreg1 - Vreg2, set VF = NOT borrow.
-	/// If Vreg1 > Vreg2, then VF is set to 1, otherwise 0. Then Vreg2 is subtracted from Vreg1, and the results stored in Vreg1.
-	fn sub(&mut self, reg1: u8, reg2: u8) -> String
-	{
-		format!("SUB V{:X}, V{:X}", reg1, reg2)
-	}
+/// Longest run of data bytes on a single `Line::Data`.
+const DATA_LINE_BYTES: usize = 8;
 
-	/// Set Vreg = Vreg SHR 1.
-	/// If the least-significant bit of Vreg is 1, then VF is set to 1, otherwise 0. Then Vreg is divided by 2.
-	fn shr(&mut self, reg: u8) -> String
-	{
-		format!("SHR V{:X}", reg)
-	}
+/// Disassemble $rom by following the control flow from the entry point at 0x200,
+/// through jumps, calls and both outcomes of skips. Whatever is not reached that way,
+/// such as sprite tables between subroutines, is written as data.
+///
+/// Code is only found where the flow analysis can see it: code reached through
+/// `JP V0, addr` shows up as data. Instructions never overlap; when a path runs into
+/// the middle of an instruction found earlier, the path is not followed further.
+/// `LD I, LONG` takes its address word along, so the address is not shown as data.
+pub fn disasm_smart(rom: &Rom) -> Vec<Line>
+{
+	let bytes = rom.bytes();
+	let word_at = |addr: u16| -> Option<u16> {
+		let offset = addr.checked_sub(0x200)? as usize;
+		if offset + 2 > bytes.len() { return None; }
+		Some((bytes[offset] as u16) << 8 | bytes[offset + 1] as u16)
+	};
+
+	let mut code: BTreeMap<u16, Instruction> = BTreeMap::new();
+	let mut claimed = vec![false; bytes.len()];
+	let mut pending = vec![0x200u16];
+	while let Some(addr) = pending.pop()
+	{
+		if code.contains_key(&addr) { continue; }
+		let instruction = match word_at(addr).map(instruction::decode) {
+			Some(Instruction::Unknown(_)) | None => continue,
+			Some(instruction) => instruction
+		};
+		if instruction == Instruction::LdILong && word_at(addr + 2).is_none() { continue; }
+
+		let span = (addr - 0x200) as usize..(addr - 0x200 + instruction.length()) as usize;
+		if claimed[span.clone()].iter().any(|&c| c) { continue; }
+		for c in &mut claimed[span] { *c = true; }
+		code.insert(addr, instruction);
+
+		if let Instruction::Call(target) = instruction { pending.push(target); }
+		pending.extend(instruction::next_addresses(addr, instruction));
+	}
+
+	let mut lines = Vec::new();
+	let mut offset = 0;
+	while offset < bytes.len()
+	{
+		let addr = 0x200 + offset as u16;
+		if let Some(&instruction) = code.get(&addr) {
+			lines.push(Line::Code(addr, instruction));
+			offset += instruction.length() as usize;
+			continue;
+		}
 
-	/// Set Vreg1 = Vreg2 - Vreg1, set VF = NOT borrow.
-	/// If Vreg2 > Vreg1, then VF is set to 1, otherwise 0. Then Vreg1 is subtracted from Vreg2, and the results stored in Vreg1.
-	fn subn(&mut self, reg1: u8, reg2: u8) -> String
-	{
-		format!("SUBN V{:X}, V{:X}", reg1, reg2)
+		let end = (offset..bytes.len()).take(DATA_LINE_BYTES).find(|&o| claimed[o]).unwrap_or_else(|| (offset + DATA_LINE_BYTES).min(bytes.len()));
+		lines.push(Line::Data(addr, bytes[offset..end].to_vec()));
+		offset = end;
 	}
+	lines
+}
 
-	/// Set Vreg = Vreg SHL 1.
-	/// If the most-significant bit of Vreg is 1, then VF is set to 1, otherwise to 0. Then Vreg is multiplied by 2.
-	fn shl(&mut self, reg: u8) -> String
-	{
-		format!("SHR V{:X}", reg)
-	}
+// ---------
+// - TESTS -
+//----------
+
+/// Records which handler `decode_opcode!` dispatched an opcode to and with which
+/// parameters.
+#[cfg(test)]
+struct DispatchProbe;
+
+#[cfg(test)]
+impl DispatchProbe {
+	fn cls(&mut self) -> String { "cls".to_string() }
+	fn ret(&mut self) -> String { "ret".to_string() }
+	fn exit(&mut self) -> String { "exit".to_string() }
+	fn sys(&mut self, addr: u16) -> String { format!("sys {:X}", addr) }
+	fn jp(&mut self, addr: u16) -> String { format!("jp {:X}", addr) }
+	fn call(&mut self, addr: u16) -> String { format!("call {:X}", addr) }
+	fn ldi(&mut self, addr: u16) -> String { format!("ldi {:X}", addr) }
+	fn jp_v0(&mut self, addr: u16) -> String { format!("jp_v0 {:X}", addr) }
+	fn se(&mut self, reg: u8, byte: u8) -> String { format!("se {:X} {:X}", reg, byte) }
+	fn sne(&mut self, reg: u8, byte: u8) -> String { format!("sne {:X} {:X}", reg, byte) }
+	fn ldx(&mut self, reg: u8, byte: u8) -> String { format!("ldx {:X} {:X}", reg, byte) }
+	fn add_byte(&mut self, reg: u8, byte: u8) -> String { format!("add_byte {:X} {:X}", reg, byte) }
+	fn rnd(&mut self, reg: u8, byte: u8) -> String { format!("rnd {:X} {:X}", reg, byte) }
+	fn se_reg(&mut self, reg1: u8, reg2: u8) -> String { format!("se_reg {:X} {:X}", reg1, reg2) }
+	fn ld(&mut self, reg1: u8, reg2: u8) -> String { format!("ld {:X} {:X}", reg1, reg2) }
+	fn or(&mut self, reg1: u8, reg2: u8) -> String { format!("or {:X} {:X}", reg1, reg2) }
+	fn and(&mut self, reg1: u8, reg2: u8) -> String { format!("and {:X} {:X}", reg1, reg2) }
+	fn xor(&mut self, reg1: u8, reg2: u8) -> String { format!("xor {:X} {:X}", reg1, reg2) }
+	fn add_reg(&mut self, reg1: u8, reg2: u8) -> String { format!("add_reg {:X} {:X}", reg1, reg2) }
+	fn sub(&mut self, reg1: u8, reg2: u8) -> String { format!("sub {:X} {:X}", reg1, reg2) }
+	fn subn(&mut self, reg1: u8, reg2: u8) -> String { format!("subn {:X} {:X}", reg1, reg2) }
+	fn sne_reg(&mut self, reg1: u8, reg2: u8) -> String { format!("sne_reg {:X} {:X}", reg1, reg2) }
+	fn drw(&mut self, xreg: u8, yreg: u8, bytes: u8) -> String { format!("drw {:X} {:X} {:X}", xreg, yreg, bytes) }
+	fn shr(&mut self, reg1: u8, reg2: u8) -> String { format!("shr {:X} {:X}", reg1, reg2) }
+	fn shl(&mut self, reg1: u8, reg2: u8) -> String { format!("shl {:X} {:X}", reg1, reg2) }
+	fn skp(&mut self, reg: u8) -> String { format!("skp {:X}", reg) }
+	fn sknp(&mut self, reg: u8) -> String { format!("sknp {:X}", reg) }
+	fn ld_dt_into_vx(&mut self, reg: u8) -> String { format!("ld_dt_into_vx {:X}", reg) }
+	fn ld_k_into_vx(&mut self, reg: u8) -> String { format!("ld_k_into_vx {:X}", reg) }
+	fn ld_vx_into_dt(&mut self, reg: u8) -> String { format!("ld_vx_into_dt {:X}", reg) }
+	fn ld_vx_into_st(&mut self, reg: u8) -> String { format!("ld_vx_into_st {:X}", reg) }
+	fn add_vx(&mut self, reg: u8) -> String { format!("add_vx {:X}", reg) }
+	fn ld_vx_digit_into_f(&mut self, reg: u8) -> String { format!("ld_vx_digit_into_f {:X}", reg) }
+	fn ld_vx_into_bcd(&mut self, reg: u8) -> String { format!("ld_vx_into_bcd {:X}", reg) }
+	fn ld_v0_to_vx_into_i(&mut self, reg: u8) -> String { format!("ld_v0_to_vx_into_i {:X}", reg) }
+	fn ld_i_into_v0_to_vx(&mut self, reg: u8) -> String { format!("ld_i_into_v0_to_vx {:X}", reg) }
+	fn plane(&mut self, mask: u8) -> String { format!("plane {}", mask) }
+	fn ld_i_long(&mut self) -> String { "ld_i_long".to_string() }
+	fn unknown_opcode(&mut self, op: u16) -> String { format!("unknown {:04X}", op) }
+}
 
-	/// Skip next instruction if Vreg1 != Vreg2.
-	fn sne_reg(&mut self, reg1: u8, reg2: u8) -> String
-	{
-		format!("SNE V{:X}, V{:X}", reg1, reg2)
-	}
+#[cfg(test)]
+fn dispatch(op: u16) -> String
+{
+	let mut probe = DispatchProbe;
+	decode_opcode!(op, probe)
+}
 
-	/// Set I = val.
-	fn ldi(&mut self, val: u16) -> String
-	{
-		format!("LD I, {:#X}", val)
+#[test]
+fn test_dispatch()
+{
+	let cases = [
+		(0x00E0, "cls"), (0x00EE, "ret"), (0x00FD, "exit"), (0x0123, "sys 123"),
+		(0x1ABC, "jp ABC"), (0x2DEF, "call DEF"),
+		(0x3A12, "se A 12"), (0x4B34, "sne B 34"), (0x5120, "se_reg 1 2"),
+		(0x6C56, "ldx C 56"), (0x7D78, "add_byte D 78"),
+		(0x8120, "ld 1 2"), (0x8121, "or 1 2"), (0x8122, "and 1 2"), (0x8123, "xor 1 2"),
+		(0x8124, "add_reg 1 2"), (0x8125, "sub 1 2"), (0x8126, "shr 1 2"), (0x8127, "subn 1 2"),
+		(0x812E, "shl 1 2"), (0x9340, "sne_reg 3 4"),
+		(0xA123, "ldi 123"), (0xB456, "jp_v0 456"), (0xC5AB, "rnd 5 AB"), (0xD12F, "drw 1 2 F"),
+		(0xE59E, "skp 5"), (0xE6A1, "sknp 6"),
+		(0xF107, "ld_dt_into_vx 1"), (0xF20A, "ld_k_into_vx 2"), (0xF315, "ld_vx_into_dt 3"),
+		(0xF418, "ld_vx_into_st 4"), (0xF51E, "add_vx 5"), (0xF629, "ld_vx_digit_into_f 6"),
+		(0xF733, "ld_vx_into_bcd 7"), (0xF855, "ld_v0_to_vx_into_i 8"), (0xF965, "ld_i_into_v0_to_vx 9"),
+		(0xF301, "plane 3"), (0xF000, "ld_i_long")
+	];
+
+	for &(op, expected) in cases.iter()
+	{
+		assert_eq!(dispatch(op), expected, "opcode {:04X}", op);
 	}
+}
 
-	/// Jump to location addr + V0.
-	fn jp_v0(&mut self, addr: u16) -> String
+#[test]
+fn test_dispatch_malformed()
+{
+	for &op in [0x5121, 0x512F, 0x8128, 0x812F, 0x9341, 0x934E, 0xE59F, 0xE6A2, 0xF100, 0xF1FF].iter()
 	{
-		format!("JP V0, {:#X}", addr)
+		assert_eq!(dispatch(op), format!("unknown {:04X}", op));
 	}
+}
 
-	/// Set Vreg = random byte && kk.
-	fn rnd(&mut self, reg: u8, byte: u8) -> String
-	{
-		format!("RND V{:X}, {:X}", reg, byte)
-	}
+#[cfg(test)]
+use ram::Ram;
 
-	/// Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-	///
-	/// The interpreter reads n bytes from memory, starting at the address stored in I. These bytes are then displayed as sprites on screen at coordinates (Vx, Vy). Sprites are XORed onto the existing screen. 
-	/// If this causes any pixels to be erased, VF is set to 1, otherwise it is set to 0. If the sprite is positioned so part of it is outside the coordinates of the display, it wraps around to the opposite side of the screen. 
-	fn drw(&mut self, xreg: u8, yreg: u8, bytes: u8) -> String
-	{
-		format!("DRW (V{:X}, V{:X}) for {:X} bytes", xreg, yreg, bytes)
-	}
+#[cfg(test)]
+fn disasm_program(program: &[u8], options: DisasmOptions) -> Vec<String>
+{
+	let mut ram = Ram::new();
+	for (addr, byte) in program.iter().enumerate() { ram.sb(0x200 + addr as u16, *byte); }
+	let mut dis = Disassembler { pc: 0x200, ram: &mut ram };
+	dis.disasm_lines(program.len() as u16, options)
+}
 
-	/// Skip next instruction if key with the value of Vreg is pressed.
-	fn skp(&mut self, reg: u8) -> String
-	{
-		format!("SKP V{:X}", reg)
-	}
+#[test]
+fn test_disasm_options()
+{
+	let program = [0x12, 0x28, 0x60, 0x0A];
 
-	/// Skip next instruction if key with the value of Vreg is not pressed.
-	fn sknp(&mut self, reg: u8) -> String
-	{
-		format!("SKNP V{:X}", reg)
-	}
+	assert!(disasm_program(&program, DisasmOptions::default()) == vec!["0x200: (0x1228) JP 0x228", "0x202: (0x600A) LD V0, 0xA"]);
 
-	/// Set Vreg = delay timer value.
-	fn ld_dt_into_vx(&mut self, reg: u8) -> String
-	{
-		format!("LD V{:X}, DT", reg)
-	}
+	let bytes = DisasmOptions { show_bytes: true, ..DisasmOptions::default() };
+	assert!(disasm_program(&program, bytes) == vec!["0x200: 12 28   JP 0x228", "0x202: 60 0A   LD V0, 0xA"]);
 
-	/// Wait for a key press, store the value of the key in Vreg.
-	fn ld_k_into_vx(&mut self, reg: u8) -> String
-	{
-		format!("LD V{:X}, K", reg)
-	}
+	let bare = DisasmOptions { show_bytes: true, show_addresses: false, ..DisasmOptions::default() };
+	assert!(disasm_program(&program, bare)[0] == "12 28   JP 0x228");
+}
 
-	/// Set delay timer = Vreg.
-	fn ld_vx_into_dt(&mut self, reg: u8) -> String
-	{
-		format!("LD DT, V{:X}", reg)
-	}
+#[test]
+fn test_disasm_json()
+{
+	let program = [0x12, 0x28, 0x60, 0x0A];
+	let mut ram = Ram::new();
+	for (addr, byte) in program.iter().enumerate() { ram.sb(0x200 + addr as u16, *byte); }
+	let mut dis = Disassembler { pc: 0x200, ram: &mut ram };
 
-	/// Set sound timer = Vreg.
-	fn ld_vx_into_st(&mut self, reg: u8) -> String
-	{
-		format!("LD ST, V{:X}", reg)
-	}
+	assert!(dis.disasm_json(program.len() as u16) ==
+		"[{\"addr\":512,\"opcode\":4648,\"mnemonic\":\"JP 0x228\"},{\"addr\":514,\"opcode\":24586,\"mnemonic\":\"LD V0, 0xA\"}]");
 
-	/// Set I = I + Vreg.
-	fn add_vx(&mut self, reg: u8) -> String
-	{
-		format!("ADD I, V{:X}", reg)
-	}
+	assert!(json_escape("a\"b\\c\n") == "a\\\"b\\\\c\\u000a");
+}
 
-	/// Set I = location of sprite for digit Vreg.
-	/// The value of I is set to the location for the hexadecimal sprite corresponding to the value of Vreg.
-	fn ld_vx_digit_into_f(&mut self, reg: u8) -> String
-	{
-		format!("LD F, V{:X}", reg)
-	}
+#[test]
+fn test_disasm_empty_rom()
+{
+	assert!(disasm_program(&[], DisasmOptions::default()).is_empty());
+	assert!(disasm_program(&[0x00, 0xE0, 0x12], DisasmOptions::default()).len() == 2); // Odd length
+}
 
-	/// Store BCD representation of Vreg in memory locations I, I+1, and I+2.
-	/// The interpreter takes the decimal value of Vreg, and places the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.
-	fn ld_vx_into_bcd(&mut self, reg: u8) -> String
-	{
-		format!("LD B, V{:X}", reg)
-	}
+#[test]
+fn test_disasm_octo_style()
+{
+	let program = [0x12, 0x28, 0x60, 0x0A, 0xF0, 0x00, 0xAB, 0xCD];
+	let octo = DisasmOptions { style: MnemonicStyle::Octo, ..DisasmOptions::default() };
 
-	/// Store registers V0 through Vreg in memory starting at location I.
-	/// The interpreter copies the values of registers V0 through Vreg into memory, starting at the address in I.
-	fn ld_v0_to_vx_into_i(&mut self, reg: u8) -> String
-	{
-		format!("LD [I], V{:X}", reg)
-	}
+	assert!(disasm_program(&program, octo) == vec!["0x200: (0x1228) jump 0x228", "0x202: (0x600A) v0 := 0x0A", "0x204: (0xF000) i := long 0xABCD"]);
+	assert!(disasm_program(&program, DisasmOptions::default())[2] == "0x204: (0xF000) LD I, LONG 0xABCD");
+}
 
-	/// Read registers V0 through Vreg from memory starting at location I.
-	/// The interpreter reads values from memory starting at location I into registers V0 through Vreg.
-	fn ld_i_into_v0_to_vx(&mut self, reg: u8) -> String
-	{
-		format!("LD V{:X}, [I]", reg)
-	}
+#[test]
+fn test_disasm_xrefs()
+{
+	// CALL 0x300 ; LD V0, 0xA ; CALL 0x300 ; JP 0x300 ; JP 0x208
+	let program = [0x23, 0x00, 0x60, 0x0A, 0x23, 0x00, 0x13, 0x00, 0x12, 0x08];
+	let xrefs = DisasmOptions { show_xrefs: true, ..DisasmOptions::default() };
+
+	assert!(disasm_program(&program, xrefs) == vec![
+		"0x200: (0x2300) CALL 0x300 ; xrefs: 0x204, 0x206",
+		"0x202: (0x600A) LD V0, 0xA",
+		"0x204: (0x2300) CALL 0x300 ; xrefs: 0x200, 0x206",
+		"0x206: (0x1300) JP 0x300 ; xrefs: 0x200, 0x204",
+		"0x208: (0x1208) JP 0x208"
+	]);
+	assert!(disasm_program(&program, DisasmOptions::default())[0] == "0x200: (0x2300) CALL 0x300");
+}
 
-	/// Handler function for unknown opcodes.
-	fn unknown_opcode(&mut self, op: u16) -> String
-	{
-		format!("Unknown opcode: 0x{:0>4X}", op)
-	}
+#[test]
+fn test_disasm_font_digits()
+{
+	// LD V1, 3 ; LD F, V1 ; ADD V1, 9 ; LD F, V1 ; SE V0, 0 ; LD V1, 1 ; LD F, V1 ; CALL 0x200 ; LD F, V1
+	let program = [0x61, 0x03, 0xF1, 0x29, 0x71, 0x09, 0xF1, 0x29, 0x30, 0x00, 0x61, 0x01, 0xF1, 0x29, 0x22, 0x00, 0xF1, 0x29];
+	let digits = DisasmOptions { show_font_digits: true, ..DisasmOptions::default() };
+
+	let lines = disasm_program(&program, digits);
+	assert!(lines[1] == "0x202: (0xF129) LD F, V1 ; font digit 3 @ 0x0F");
+	assert!(lines[3] == "0x206: (0xF129) LD F, V1 ; font digit C @ 0x3C");
+	assert!(lines[6] == "0x20C: (0xF129) LD F, V1"); // LD V1, 1 may have been skipped
+	assert!(lines[8] == "0x210: (0xF129) LD F, V1"); // The subroutine may change V1
+	assert!(disasm_program(&program, DisasmOptions::default())[1] == "0x202: (0xF129) LD F, V1");
+}
 
-	/// Run the disassembly and print the results.
-	/// Runs until program counter reaches the end of the ROM.
-	pub fn disasm(&mut self, rom_length: u16) {
-		println!("");
-		println!("===");
+#[test]
+fn test_disasm_smart()
+{
+	let program = [
+		0xA2, 0x06, // 0x200: LD I, 0x206
+		0x22, 0x0C, // 0x202: CALL 0x20C
+		0x12, 0x04, // 0x204: JP 0x204
+		0xF0, 0x90, 0xF0, 0x90, 0xF0, 0x00, // 0x206: sprite of 8, padding
+		0xD0, 0x15, // 0x20C: DRW V0, V1, 5
+		0x00, 0xEE, // 0x20E: RET
+		0x80, 0xF0, 0x80, 0xF0, 0x80, 0xF0, 0x80, 0xF0, 0x80, 0xF0 // 0x210: unreferenced table
+	];
+	let lines = disasm_smart(&Rom::from_bytes(&program, "smart".to_string()));
+
+	assert!(lines == vec![
+		Line::Code(0x200, Instruction::Ldi(0x206)),
+		Line::Code(0x202, Instruction::Call(0x20C)),
+		Line::Code(0x204, Instruction::Jp(0x204)),
+		Line::Data(0x206, vec![0xF0, 0x90, 0xF0, 0x90, 0xF0, 0x00]),
+		Line::Code(0x20C, Instruction::Drw(0x0, 0x1, 0x5)),
+		Line::Code(0x20E, Instruction::Ret),
+		Line::Data(0x210, vec![0x80, 0xF0, 0x80, 0xF0, 0x80, 0xF0, 0x80, 0xF0]),
+		Line::Data(0x218, vec![0x80, 0xF0])
+	]);
+	assert!(lines[3].to_string() == "0x206: DB 0xF0, 0x90, 0xF0, 0x90, 0xF0, 0x00");
+	assert!(lines[4].to_string() == "0x20C: DRW (V0, V1) for 5 bytes");
+}
 
-		loop {
-			let op = self.next_opcode();
-			println!("{:#X}: (0x{:0>4X}) {}", op.0, op.1, decode_opcode!(op.1, self));
-			if self.pc >= (0x200 + rom_length) { break; }
-		}
-	}
-}
\ No newline at end of file
+#[test]
+fn test_disasm_smart_overlap()
+{
+	// JP 0x203 points between two instructions, so nothing is decoded there
+	// 0x200: SE V0, 0 ; 0x202: JP 0x203 ; 0x204: JP 0x204
+	let program = [0x30, 0x00, 0x12, 0x03, 0x12, 0x04];
+	let lines = disasm_smart(&Rom::from_bytes(&program, "overlap".to_string()));
+
+	assert!(lines == vec![
+		Line::Code(0x200, Instruction::Se(0x0, 0x00)),
+		Line::Code(0x202, Instruction::Jp(0x203)),
+		Line::Code(0x204, Instruction::Jp(0x204))
+	]);
+}
+
+#[test]
+fn test_disasm_unknown_handling()
+{
+	// CLS ; JP 0x200 ; followed by the data bytes FF FF 01 02
+	let program = [0x00, 0xE0, 0x12, 0x00, 0xFF, 0xFF, 0x01, 0x02];
+
+	let all = disasm_program(&program, DisasmOptions::default());
+	assert!(all.len() == 4);
+	assert!(all[2] == "0x204: (0xFFFF) Unknown opcode: 0xFFFF");
+
+	let stop = DisasmOptions { stop_on_unknown: true, ..DisasmOptions::default() };
+	assert!(disasm_program(&program, stop) == vec!["0x200: (0x00E0) CLS", "0x202: (0x1200) JP 0x200"]);
+
+	let data = DisasmOptions { skip_unknown_as_data: true, ..DisasmOptions::default() };
+	let lines = disasm_program(&program, data);
+	assert!(lines.len() == 4);
+	assert!(lines[2] == "0x204: (0xFFFF) DB 0xFF, 0xFF");
+	assert!(lines[3] == "0x206: (0x0102) SYS 0x0102"); // Decodes, so left as is
+}
@@ -0,0 +1,496 @@
+//! Display module for the CHIP-8 emulation
+//!
+//! Provides the `Display` trait that provides the interface the emulator core
+//! expects for drawing, and `TestDisplay`, an in-memory implementation of it.
+
+/// Width of the standard CHIP-8 display in pixels.
+pub const WIDTH: u16 = 64;
+
+/// Height of the standard CHIP-8 display in pixels.
+pub const HEIGHT: u16 = 32;
+
+/// Display resolutions supported by the CHIP-8 variants.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resolution {
+	/// Standard CHIP-8 resolution of 64x32 pixels
+	Low,
+	/// SuperCHIP high resolution mode of 128x64 pixels
+	High,
+	/// ETI-660 resolution of 64x48 pixels
+	Eti660
+}
+
+impl Resolution {
+	/// Width and height of the resolution in pixels.
+	pub fn dimensions(&self) -> (u16, u16)
+	{
+		match *self {
+			Resolution::Low => (WIDTH, HEIGHT),
+			Resolution::High => (128, 64),
+			Resolution::Eti660 => (64, 48)
+		}
+	}
+
+	/// Find the resolution with the given width and height.
+	pub fn from_dimensions(dimensions: (u16, u16)) -> Option<Resolution>
+	{
+		[Resolution::Low, Resolution::High, Resolution::Eti660].iter().cloned().find(|r| r.dimensions() == dimensions)
+	}
+}
+
+/// How sprite pixels are combined with the pixels already on the display.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DrawMode {
+	/// Sprite pixels are XORed onto the display. This is what CHIP-8 uses.
+	Xor,
+	/// Sprite pixels are lit, other pixels are left as is.
+	Or,
+	/// Only pixels that are lit in both the sprite and the display stay lit.
+	And
+}
+
+/// `Display` -trait defines the monochrome display the CHIP-8 emulation core expects.
+/// Coordinates given to the display are always within the display bounds.
+pub trait Display {
+	/// Turn off every pixel on the display.
+	fn clear(&mut self);
+
+	/// Returns true if the pixel at (x, y) is lit.
+	fn get_pixel(&self, x: u16, y: u16) -> bool;
+
+	/// Light up (true) or turn off (false) the pixel at (x, y).
+	fn set_pixel(&mut self, x: u16, y: u16, on: bool);
+
+	/// Returns the current width and height of the display in pixels.
+	fn resolution(&self) -> (u16, u16);
+
+	/// Switch the display to the given resolution. The display is cleared.
+	fn set_resolution(&mut self, resolution: Resolution);
+
+	/// Draw an 8 pixels wide sprite with its top left corner at (x, y), one byte per row.
+	/// Parts of the sprite outside the display wrap around to the opposite side.
+	/// Returns true if any lit pixel was turned off (collision).
+	///
+	/// Only `DrawMode::Xor` is used by the emulation, the other modes are meant for tooling.
+	fn blit_sprite(&mut self, x: u16, y: u16, rows: &[u8], mode: DrawMode) -> bool
+	{
+		self.blit_sprite_on_plane(0, x, y, rows, mode)
+	}
+
+	/// XOR the sprite $sprite, one byte per row, onto the display at (x, y) the way `DRW`
+	/// does. Returns true if any lit pixel was erased (collision). Backends that can draw
+	/// faster than pixel by pixel override this rather than `blit_sprite`.
+	fn blit(&mut self, x: u8, y: u8, sprite: &[u8]) -> bool
+	{
+		self.blit_sprite(x as u16, y as u16, sprite, DrawMode::Xor)
+	}
+
+	/// Returns true if the pixel at (x, y) is lit on bit-plane $plane. XO-CHIP draws
+	/// on two planes, plane 0 being the one `get_pixel` reads. Displays without a
+	/// second plane report its pixels as unlit.
+	fn get_plane_pixel(&self, plane: u8, x: u16, y: u16) -> bool
+	{
+		if plane == 0 { self.get_pixel(x, y) } else { false }
+	}
+
+	/// Light up or turn off the pixel at (x, y) on bit-plane $plane. Displays without
+	/// a second plane ignore it.
+	fn set_plane_pixel(&mut self, plane: u8, x: u16, y: u16, on: bool)
+	{
+		if plane == 0 { self.set_pixel(x, y, on); }
+	}
+
+	/// Number of lit pixels the latest sprite draw turned off. `VF` only tells whether
+	/// there was a collision, this tells how many pixels collided. Displays that do
+	/// not keep the count report 0.
+	fn last_draw_erased_count(&self) -> u32 { 0 }
+
+	/// Store the value `last_draw_erased_count` reports. Called by `blit_sprite_on_plane`
+	/// after every sprite, and by `DRW` with the total of sprites drawn in several parts.
+	fn set_last_draw_erased_count(&mut self, _count: u32) {}
+
+	/// Draw a sprite like `blit_sprite`, but on bit-plane $plane.
+	fn blit_sprite_on_plane(&mut self, plane: u8, x: u16, y: u16, rows: &[u8], mode: DrawMode) -> bool
+	{
+		let (width, height) = self.resolution();
+		let mut erased = 0;
+
+		for (row, byte) in rows.iter().enumerate()
+		{
+			for col in 0..8
+			{
+				let px = (x + col) % width;
+				let py = (y + row as u16) % height;
+				let bit = byte & (0x80 >> col) != 0;
+				let lit = self.get_plane_pixel(plane, px, py);
+				let on = match mode {
+					DrawMode::Xor => lit ^ bit,
+					DrawMode::Or => lit | bit,
+					DrawMode::And => lit & bit
+				};
+
+				if lit && !on { erased += 1; }
+				if on != lit { self.set_plane_pixel(plane, px, py, on); }
+			}
+		}
+
+		self.set_last_draw_erased_count(erased);
+		erased > 0
+	}
+
+	/// Returns the number of currently lit pixels on the display. Useful for
+	/// verifying the output of test ROMs.
+	fn count_lit_pixels(&self) -> usize
+	{
+		let (width, height) = self.resolution();
+		let mut count = 0;
+		for y in 0..height {
+			for x in 0..width {
+				if self.get_pixel(x, y) { count += 1; }
+			}
+		}
+		count
+	}
+
+	/// Development aid for checking a new `Display` backend without a ROM: clears the
+	/// display and draws a one pixel border around the edges of the current resolution
+	/// with a checkerboard inside it.
+	fn draw_test_pattern(&mut self)
+	{
+		let (width, height) = self.resolution();
+		self.clear();
+		for y in 0..height {
+			for x in 0..width {
+				let border = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+				if border || (x + y) % 2 == 0 { self.set_pixel(x, y, true); }
+			}
+		}
+	}
+
+	/// Returns the pixel states in row-major order.
+	fn pixels(&self) -> Vec<bool>
+	{
+		let (width, height) = self.resolution();
+		let mut pixels = Vec::with_capacity((width * height) as usize);
+		for y in 0..height {
+			for x in 0..width { pixels.push(self.get_pixel(x, y)); }
+		}
+		pixels
+	}
+
+	/// Returns the pixels that differ from $prev, a framebuffer captured earlier with
+	/// `pixels`, as (row-major index, new state) pairs. A compact way to stream the display.
+	/// Pixels beyond the end of $prev count as unlit before.
+	fn framebuffer_delta(&self, prev: &[bool]) -> Vec<(u16, bool)>
+	{
+		self.pixels().into_iter().enumerate()
+			.filter(|&(index, on)| prev.get(index).cloned().unwrap_or(false) != on)
+			.map(|(index, on)| (index as u16, on))
+			.collect()
+	}
+
+	/// Called once when the emulation ends with `EmulatorConfig::blank_on_exit` set.
+	/// Turns off every pixel so the last frame does not linger; backends with a
+	/// window also present the blank screen before it closes.
+	fn shutdown(&mut self)
+	{
+		self.clear();
+	}
+
+	/// Render the display as text, one line per row with `#` for lit and ` ` for
+	/// unlit pixels. Handy for logging the screen where images are not practical.
+	fn to_ascii(&self) -> String
+	{
+		let (width, height) = self.resolution();
+		let mut text = String::with_capacity(((width + 1) * height) as usize);
+		for y in 0..height {
+			for x in 0..width {
+				text.push(if self.get_pixel(x, y) { '#' } else { ' ' });
+			}
+			text.push('\n');
+		}
+		text
+	}
+}
+
+/// In-memory display without any output. The pixels can be inspected
+/// through the `Display` trait, which makes it suitable for tests.
+pub struct TestDisplay {
+	/// Pixel states in row-major order
+	pixels: Vec<bool>,
+	/// Pixel states of the second XO-CHIP bit-plane in row-major order
+	second_plane: Vec<bool>,
+	/// Current resolution
+	resolution: Resolution,
+	/// Pixels erased by the latest sprite draw
+	erased: u32
+}
+
+impl TestDisplay
+{
+	pub fn new() -> TestDisplay
+	{
+		TestDisplay { pixels: vec![false; (WIDTH * HEIGHT) as usize], second_plane: vec![false; (WIDTH * HEIGHT) as usize], resolution: Resolution::Low, erased: 0 }
+	}
+
+	/// Returns the pixel states in row-major order.
+	pub fn framebuffer(&self) -> &[bool]
+	{
+		&self.pixels
+	}
+
+	/// Returns the pixel states of bit-plane $plane in row-major order.
+	pub fn plane_framebuffer(&self, plane: u8) -> &[bool]
+	{
+		if plane == 0 { &self.pixels } else { &self.second_plane }
+	}
+}
+
+impl Default for TestDisplay
+{
+	fn default() -> TestDisplay { TestDisplay::new() }
+}
+
+impl Display for TestDisplay
+{
+	fn clear(&mut self)
+	{
+		for pixel in self.pixels.iter_mut() { *pixel = false; }
+		for pixel in self.second_plane.iter_mut() { *pixel = false; }
+	}
+
+	fn get_pixel(&self, x: u16, y: u16) -> bool
+	{
+		self.pixels[(y * self.resolution().0 + x) as usize]
+	}
+
+	fn set_pixel(&mut self, x: u16, y: u16, on: bool)
+	{
+		let width = self.resolution().0;
+		self.pixels[(y * width + x) as usize] = on;
+	}
+
+	fn resolution(&self) -> (u16, u16)
+	{
+		self.resolution.dimensions()
+	}
+
+	fn set_resolution(&mut self, resolution: Resolution)
+	{
+		let (width, height) = resolution.dimensions();
+		self.resolution = resolution;
+		self.pixels = vec![false; (width * height) as usize];
+		self.second_plane = vec![false; (width * height) as usize];
+	}
+
+	fn get_plane_pixel(&self, plane: u8, x: u16, y: u16) -> bool
+	{
+		let index = (y * self.resolution().0 + x) as usize;
+		if plane == 0 { self.pixels[index] } else { self.second_plane[index] }
+	}
+
+	fn set_plane_pixel(&mut self, plane: u8, x: u16, y: u16, on: bool)
+	{
+		let index = (y * self.resolution().0 + x) as usize;
+		if plane == 0 { self.pixels[index] = on; } else { self.second_plane[index] = on; }
+	}
+
+	fn count_lit_pixels(&self) -> usize
+	{
+		self.pixels.iter().filter(|p| **p).count()
+	}
+
+	fn last_draw_erased_count(&self) -> u32 { self.erased }
+
+	fn set_last_draw_erased_count(&mut self, count: u32) { self.erased = count; }
+}
+
+/// Display that discards everything drawn on it. Always reports the standard
+/// resolution and no lit pixels. Useful for benchmarking the emulation core.
+pub struct NullDisplay;
+
+impl Display for NullDisplay
+{
+	fn clear(&mut self) {}
+
+	fn get_pixel(&self, _x: u16, _y: u16) -> bool { false }
+
+	fn set_pixel(&mut self, _x: u16, _y: u16, _on: bool) {}
+
+	fn resolution(&self) -> (u16, u16) { Resolution::Low.dimensions() }
+
+	fn set_resolution(&mut self, _resolution: Resolution) {}
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[test]
+fn test_count_lit_pixels()
+{
+	let mut display = TestDisplay::new();
+	assert!(display.count_lit_pixels() == 0);
+
+	display.set_pixel(0, 0, true);
+	display.set_pixel(WIDTH - 1, HEIGHT - 1, true);
+	assert!(display.count_lit_pixels() == 2);
+
+	display.clear();
+	assert!(display.count_lit_pixels() == 0);
+}
+
+#[test]
+fn test_blit_sprite_xor()
+{
+	let mut display = TestDisplay::new();
+
+	assert!(!display.blit_sprite(0, 0, &[0xF0, 0x90], DrawMode::Xor));
+	assert!(display.count_lit_pixels() == 6);
+
+	// Overlaps the top row, which gets partially erased
+	assert!(display.blit_sprite(2, 0, &[0xC0], DrawMode::Xor));
+	assert!(display.count_lit_pixels() == 4);
+	assert!(display.get_pixel(0, 0) && display.get_pixel(1, 0));
+	assert!(!display.get_pixel(2, 0) && !display.get_pixel(3, 0));
+}
+
+#[test]
+fn test_blit_sprite_or()
+{
+	let mut display = TestDisplay::new();
+
+	assert!(!display.blit_sprite(0, 0, &[0xF0], DrawMode::Or));
+	assert!(!display.blit_sprite(2, 0, &[0xFF], DrawMode::Or)); // Never erases
+	assert!(display.count_lit_pixels() == 10); // Pixels 0 to 9 on the top row
+}
+
+#[test]
+fn test_blit_sprite_and()
+{
+	let mut display = TestDisplay::new();
+
+	display.blit_sprite(0, 0, &[0xF0], DrawMode::Or);
+	assert!(display.blit_sprite(0, 0, &[0x30], DrawMode::And));
+	assert!(display.count_lit_pixels() == 2);
+	assert!(display.get_pixel(2, 0) && display.get_pixel(3, 0));
+}
+
+#[test]
+fn test_resolution()
+{
+	let mut display = TestDisplay::new();
+	assert!(display.resolution() == (64, 32));
+
+	display.set_resolution(Resolution::High);
+	assert!(display.resolution() == (128, 64));
+	assert!(display.framebuffer().len() == 128 * 64);
+
+	// Sprites wrap at the high resolution edges
+	display.blit_sprite(124, 63, &[0xFF], DrawMode::Xor);
+	assert!(display.get_pixel(127, 63));
+	assert!(display.get_pixel(0, 63));
+	assert!(!display.get_pixel(64, 63));
+
+	display.set_resolution(Resolution::Low);
+	assert!(display.resolution() == (64, 32));
+	assert!(display.count_lit_pixels() == 0);
+}
+
+#[test]
+fn test_to_ascii()
+{
+	let mut display = TestDisplay::new();
+	display.set_pixel(0, 0, true);
+	display.set_pixel(2, 1, true);
+	display.set_pixel(63, 31, true);
+
+	let text = display.to_ascii();
+	let lines: Vec<&str> = text.lines().collect();
+	assert!(lines.len() == 32);
+	assert!(lines.iter().all(|line| line.len() == 64));
+	assert!(lines[0].starts_with("#  "));
+	assert!(lines[1].starts_with("  #"));
+	assert!(lines[31].ends_with(" #"));
+	assert!(text.matches('#').count() == 3);
+
+	assert!(NullDisplay.to_ascii().trim().is_empty());
+}
+
+#[test]
+fn test_draw_test_pattern()
+{
+	let mut display = TestDisplay::new();
+	display.set_pixel(5, 6, true);
+	display.draw_test_pattern();
+
+	// 188 border pixels and half of the 62x30 interior
+	assert!(display.count_lit_pixels() == 188 + 930);
+	assert!(display.get_pixel(0, 0) && display.get_pixel(63, 31));
+	assert!(display.get_pixel(2, 2) && !display.get_pixel(2, 3));
+	assert!(!display.get_pixel(5, 6)); // Cleared first
+
+	display.set_resolution(Resolution::High);
+	display.draw_test_pattern();
+	assert!(display.count_lit_pixels() == 2 * 128 + 2 * 62 + 126 * 62 / 2);
+}
+
+#[test]
+fn test_shutdown()
+{
+	let mut display = TestDisplay::new();
+	display.draw_test_pattern();
+	assert!(display.count_lit_pixels() > 0);
+
+	display.shutdown();
+	assert!(display.count_lit_pixels() == 0);
+}
+
+#[test]
+fn test_blit()
+{
+	let mut display = TestDisplay::new();
+
+	assert!(!display.blit(2, 3, &[0xF0, 0x90]));
+	assert!(display.count_lit_pixels() == 6);
+	assert!(display.get_pixel(2, 3) && display.get_pixel(5, 4) && !display.get_pixel(3, 4));
+
+	assert!(!display.blit(10, 10, &[0xFF])); // Elsewhere, no collision
+	assert!(display.blit(2, 4, &[0x80])); // Erases (2, 4)
+	assert!(!display.get_pixel(2, 4));
+	assert!(display.blit(2, 3, &[0xF0, 0x10]));
+	assert!(display.count_lit_pixels() == 8);
+}
+
+#[test]
+fn test_blit_on_plane()
+{
+	let mut display = TestDisplay::new();
+	assert!(!display.blit_sprite_on_plane(1, 0, 0, &[0xF0], DrawMode::Xor));
+	assert!(display.count_lit_pixels() == 0); // Only plane 0 is counted
+	assert!(display.plane_framebuffer(1).iter().filter(|p| **p).count() == 4);
+	assert!(display.blit_sprite_on_plane(1, 0, 0, &[0x80], DrawMode::Xor));
+
+	display.clear();
+	assert!(display.plane_framebuffer(1).iter().all(|p| !p));
+
+	// Displays without a second plane ignore it
+	let mut null = NullDisplay;
+	assert!(!null.blit_sprite_on_plane(1, 0, 0, &[0xFF], DrawMode::Xor));
+}
+
+#[test]
+fn test_framebuffer_delta()
+{
+	let mut display = TestDisplay::new();
+	display.set_pixel(1, 0, true);
+	let prev = display.pixels();
+	assert!(display.framebuffer_delta(&prev).is_empty());
+
+	display.set_pixel(0, 0, true);
+	display.set_pixel(1, 0, false);
+	display.set_pixel(3, 2, true);
+	assert!(display.framebuffer_delta(&prev) == vec![(0, true), (1, false), (2 * 64 + 3, true)]);
+
+	assert!(display.framebuffer_delta(&[]).len() == 2); // Lit pixels only
+}
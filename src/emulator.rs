@@ -0,0 +1,460 @@
+//! Self-contained emulator for frontends that drive the emulation themselves.
+//!
+//! `Emulator` owns the memory, input, display and CPU, so a frontend only has to call
+//! `step_frame` at the timer rate, forward key events and draw the framebuffer.
+
+use rom::Rom;
+#[cfg(test)]
+use rom::logo_rom;
+use ram::Ram;
+use cpu::{Cpu, CpuContext};
+use config::{EmulatorConfig, Platform};
+use input::{keys_to_bitmask, ArrayInput, Input};
+use display::{Display, TestDisplay};
+use audio::{BeepSustain, Beeper, WavWriter, SAMPLE_RATE};
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::path::Path;
+use {check_rom, run_frame, EmulatorError};
+
+/// A CHIP-8 machine running a single ROM.
+pub struct Emulator {
+	ram: Ram,
+	input: ArrayInput,
+	display: TestDisplay,
+	/// CPU state between frames. Only None while a frame is running.
+	context: Option<CpuContext>,
+	config: EmulatorConfig,
+	beeping: bool,
+	sustain: BeepSustain,
+	/// Trace lines of the CPU not yet collected with `take_trace`
+	trace: Vec<String>,
+	/// Destination of the recorded audio, if recording
+	audio: Option<(WavWriter<File>, Beeper)>
+}
+
+/// Memory holding the font and $rom at 0x200: 64 kilobytes for XO-CHIP, whose
+/// `LD I, LONG` reaches past the usual 4.
+fn ram_for(rom: &Rom, config: &EmulatorConfig) -> Ram
+{
+	let ram = Ram::new_from_rom(rom);
+	if config.quirks.platform != Platform::XoChip { return ram; }
+
+	let mut extended = Ram::new_extended();
+	let image = ram.as_slice();
+	extended.as_mut_slice()[..image.len()].copy_from_slice(image);
+	extended
+}
+
+impl Emulator
+{
+	/// Create an emulator running $rom with $config. Fails if the ROM is empty.
+	pub fn new(rom: &Rom, config: EmulatorConfig) -> Result<Emulator, EmulatorError>
+	{
+		check_rom(rom)?;
+		let mut ram = ram_for(rom, &config);
+		let input = ArrayInput::new();
+		let mut display = TestDisplay::new();
+
+		let context = {
+			let mut cpu = Cpu::new(&mut ram, &input, &mut display);
+			cpu.configure(&config);
+			cpu.suspend()
+		};
+
+		Ok(Emulator { ram, input, display, context: Some(context), config, beeping: false, sustain: BeepSustain::new(config.min_beep_frames), trace: Vec::new(), audio: None })
+	}
+
+	/// Run a single frame: execute `cycles_per_frame` cycles worth of opcodes and
+	/// update the timers. Does nothing once the program has exited; the frame in which
+	/// it exits blanks the display when `EmulatorConfig::blank_on_exit` is set.
+	pub fn step_frame(&mut self) -> Result<(), EmulatorError>
+	{
+		let context = self.context.take().expect("CPU context is only taken during a frame");
+		let mut cpu = Cpu::resume(&mut self.ram, &self.input, &mut self.display, context);
+
+		let was_halted = cpu.is_halted();
+		let result = if was_halted { Ok(()) } else { run_frame(&mut cpu, &self.config, self.input.turbo_held(), &mut |_| {}) };
+		let exited = !was_halted && cpu.is_halted();
+		self.beeping = self.sustain.update(cpu.is_beeping());
+		self.trace.append(&mut cpu.take_trace());
+		self.context = Some(cpu.suspend());
+
+		if exited && self.config.blank_on_exit { self.display.shutdown(); }
+
+		result.map_err(EmulatorError::from)?;
+		self.record_frame().map_err(|err| EmulatorError::Audio(err.to_string()))
+	}
+
+	/// Write the beeper output of every following frame to a WAV file at $path,
+	/// replacing any recording in progress. A frame lasts one tick of the timers.
+	pub fn record_audio<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()>
+	{
+		let writer = WavWriter::new(File::create(path)?)?;
+		self.audio = Some((writer, Beeper::new()));
+		Ok(())
+	}
+
+	/// Append the samples of the last frame to the audio recording.
+	fn record_frame(&mut self) -> io::Result<()>
+	{
+		if let Some((ref mut writer, ref mut beeper)) = self.audio
+		{
+			let mut samples = Vec::new();
+			// The CPU treats a timer frequency of 0 as 1 Hz, see `Cpu::set_timer_hz`
+			beeper.fill(self.beeping, (SAMPLE_RATE / self.config.timer_hz.max(1)) as usize, &mut samples);
+			writer.write_samples(&samples)?;
+		}
+		Ok(())
+	}
+
+	/// Run a single frame like `step_frame` with the keys in $keys held, for scripted
+	/// tests driving a program one frame at a time. The keys set with `key_down` and
+	/// `key_up` are back in effect afterwards.
+	pub fn step_frame_with_input(&mut self, keys: [bool;16]) -> Result<(), EmulatorError>
+	{
+		let held = self.input.keys_bitmask();
+		self.input.set_keys_bitmask(keys_to_bitmask(keys));
+		let result = self.step_frame();
+		self.input.set_keys_bitmask(held);
+		result
+	}
+
+	/// Replace the running program with $rom. Memory is reset to the font and the new
+	/// program at 0x200, the screen is cleared and the CPU starts over, while the
+	/// configuration and held keys are kept. Fails if the ROM is empty, leaving the
+	/// current program running.
+	pub fn reload(&mut self, rom: &Rom) -> Result<(), EmulatorError>
+	{
+		check_rom(rom)?;
+		self.ram = ram_for(rom, &self.config);
+		self.display.clear();
+		self.beeping = false;
+		self.sustain = BeepSustain::new(self.config.min_beep_frames);
+		self.trace.clear();
+
+		let mut cpu = Cpu::new(&mut self.ram, &self.input, &mut self.display);
+		cpu.configure(&self.config);
+		self.context = Some(cpu.suspend());
+		Ok(())
+	}
+
+	/// Set registers V0 to VF to $v before the program runs, for test ROMs that expect
+	/// a known starting state instead of zeroed registers.
+	pub fn with_initial_registers(&mut self, v: [u8;16])
+	{
+		let context = self.context.take().expect("CPU context is only taken during a frame");
+		let mut cpu = Cpu::resume(&mut self.ram, &self.input, &mut self.display, context);
+		for (reg, &value) in v.iter().enumerate()
+		{
+			cpu.set_register(reg as u8, value).expect("V0 to VF are valid registers");
+		}
+		self.context = Some(cpu.suspend());
+	}
+
+	/// Copy $bytes into memory starting at $addr, on top of the font and the program.
+	/// Bytes past the end of memory are dropped. Returns the number of bytes written.
+	pub fn preload_memory(&mut self, bytes: &[u8], addr: u16) -> usize
+	{
+		let context = self.context.take().expect("CPU context is only taken during a frame");
+		let mut cpu = Cpu::resume(&mut self.ram, &self.input, &mut self.display, context);
+		let written = cpu.load_at(bytes, addr);
+		self.context = Some(cpu.suspend());
+		written
+	}
+
+	/// Returns the trace lines collected since the last call, such as the warnings of
+	/// `EmulatorConfig::strict` and `detect_smc`, and clears them.
+	pub fn take_trace(&mut self) -> Vec<String>
+	{
+		mem::take(&mut self.trace)
+	}
+
+	/// Address of the next opcode to execute.
+	pub fn pc(&self) -> u16
+	{
+		self.context.as_ref().expect("CPU context is only taken during a frame").pc()
+	}
+
+	/// Registers V0 to VF.
+	pub fn registers(&self) -> [u8;16]
+	{
+		self.context.as_ref().expect("CPU context is only taken during a frame").registers()
+	}
+
+	/// Mark key $k (0x0 - 0xF) as pressed.
+	pub fn key_down(&mut self, k: u8)
+	{
+		self.input.set(k, true);
+	}
+
+	/// Hold (true) or release (false) the turbo key. While held, frames run
+	/// `EmulatorConfig::turbo_factor` times as many cycles.
+	pub fn set_turbo(&mut self, held: bool)
+	{
+		self.input.set_turbo(held);
+	}
+
+	/// Mark key $k (0x0 - 0xF) as released.
+	pub fn key_up(&mut self, k: u8)
+	{
+		self.input.set(k, false);
+	}
+
+	/// Pixels of the display in row-major order.
+	pub fn framebuffer(&self) -> &[bool]
+	{
+		self.display.framebuffer()
+	}
+
+	/// Whether the sound timer was running at the end of the last frame, or the beep is
+	/// sustained by `EmulatorConfig::min_beep_frames`.
+	pub fn is_beeping(&self) -> bool
+	{
+		self.beeping
+	}
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[test]
+fn test_emulator_runs_digits_rom()
+{
+	let rom = Rom::from_bytes(include_bytes!("../tests/roms/digits.ch8"), "digits.ch8".to_string());
+	let mut emulator = Emulator::new(&rom, EmulatorConfig::default()).unwrap();
+
+	emulator.step_frame().unwrap();
+	let lit = emulator.framebuffer().iter().filter(|&&on| on).count();
+	assert!(lit > 0);
+	assert!(!emulator.is_beeping());
+
+	// The ROM waits for a key after drawing 0 - 7; pressing A draws it on the bottom row
+	for _ in 0..10 { emulator.step_frame().unwrap(); }
+	emulator.key_down(0xA);
+	for _ in 0..10 { emulator.step_frame().unwrap(); }
+	emulator.key_up(0xA);
+	assert!(emulator.framebuffer().iter().filter(|&&on| on).count() == 111);
+	assert!(emulator.framebuffer()[24 * 64]); // Bottom left leg of A
+}
+
+#[test]
+fn test_emulator_beeps()
+{
+	// LD V0, 0x20 ; LD ST, V0 ; JP 0x204
+	let rom = Rom::from_bytes(&[0x60, 0x20, 0xF0, 0x18, 0x12, 0x04], "beep".to_string());
+	let mut emulator = Emulator::new(&rom, EmulatorConfig::default()).unwrap();
+
+	emulator.step_frame().unwrap();
+	assert!(emulator.is_beeping());
+
+	assert!(Emulator::new(&Rom::from_bytes(&[], "empty".to_string()), EmulatorConfig::default()).is_err());
+}
+
+#[test]
+fn test_emulator_min_beep_frames()
+{
+	// LD V0, 0x2 ; LD ST, V0 ; JP 0x204
+	let rom = Rom::from_bytes(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04], "click".to_string());
+	let config = EmulatorConfig { min_beep_frames: 3, ..EmulatorConfig::default() };
+	let mut emulator = Emulator::new(&rom, config).unwrap();
+
+	let mut frames = Vec::new();
+	for _ in 0..5
+	{
+		emulator.step_frame().unwrap();
+		frames.push(emulator.is_beeping());
+	}
+	assert!(frames == vec![true, true, true, false, false]);
+}
+
+#[test]
+fn test_emulator_reload()
+{
+	// LD V0, 0x20 ; LD ST, V0 ; JP 0x204
+	let beep = Rom::from_bytes(&[0x60, 0x20, 0xF0, 0x18, 0x12, 0x04], "beep".to_string());
+	let mut emulator = Emulator::new(&beep, EmulatorConfig::default()).unwrap();
+	emulator.step_frame().unwrap();
+	assert!(emulator.pc() == 0x204);
+	assert!(emulator.is_beeping());
+
+	// LD I, 0x000 ; DRW V0, V0, 5 ; JP 0x204
+	let zero = Rom::from_bytes(&[0xA0, 0x00, 0xD0, 0x05, 0x12, 0x04], "zero".to_string());
+	emulator.reload(&zero).unwrap();
+	assert!(emulator.pc() == 0x200);
+	assert!(!emulator.is_beeping());
+
+	emulator.step_frame().unwrap();
+	assert!(emulator.pc() == 0x204);
+	assert!(!emulator.is_beeping()); // The old program is gone
+	assert!(emulator.framebuffer().iter().filter(|&&on| on).count() == 14); // Font digit 0 survived
+
+	assert!(emulator.reload(&Rom::from_bytes(&[], "empty".to_string())).is_err());
+	assert!(emulator.pc() == 0x204);
+}
+
+#[test]
+fn test_emulator_runs_logo_rom()
+{
+	let mut emulator = Emulator::new(&logo_rom(), EmulatorConfig::default()).unwrap();
+	for _ in 0..10 { emulator.step_frame().unwrap(); }
+
+	assert!(emulator.pc() == 0x218); // Idling after drawing
+	assert!(emulator.framebuffer().iter().filter(|&&on| on).count() == 55);
+	assert!(emulator.framebuffer()[13 * 64 + 20]); // Top left corner of the C
+}
+
+#[test]
+fn test_emulator_records_audio()
+{
+	use std::env;
+	use std::fs;
+
+	// LD V0, 0x20 ; LD ST, V0 ; JP 0x204
+	let rom = Rom::from_bytes(&[0x60, 0x20, 0xF0, 0x18, 0x12, 0x04], "beep".to_string());
+	let mut emulator = Emulator::new(&rom, EmulatorConfig::default()).unwrap();
+	let path = env::temp_dir().join(format!("chit8-beep-{}.wav", ::std::process::id()));
+	emulator.record_audio(&path).unwrap();
+
+	for _ in 0..60 { emulator.step_frame().unwrap(); }
+	let wav = fs::read(&path).unwrap();
+	fs::remove_file(&path).unwrap();
+
+	// One second of audio. The sound timer is set during the first frame and already
+	// ticks once at its end, leaving 0x1F frames of beeping.
+	let samples_per_frame = (SAMPLE_RATE / 60) as usize;
+	assert!(wav.len() == 44 + 2 * 60 * samples_per_frame);
+	let frames: Vec<bool> = wav[44..].chunks(2 * samples_per_frame).map(|frame| frame.iter().any(|&b| b != 0)).collect();
+	assert!(frames.iter().take_while(|&&on| on).count() == 0x1F);
+	assert!(frames.iter().skip(0x1F).all(|&on| !on));
+}
+
+#[test]
+fn test_emulator_step_frame_with_input()
+{
+	// LD V1, K ; ADD V2, 1 ; JP 0x202
+	let rom = Rom::from_bytes(&[0xF1, 0x0A, 0x72, 0x01, 0x12, 0x02], "wait".to_string());
+	let mut emulator = Emulator::new(&rom, EmulatorConfig::default()).unwrap();
+	emulator.key_down(0x3);
+
+	let mut keys = [false;16];
+	emulator.step_frame_with_input(keys).unwrap();
+	assert!(emulator.pc() == 0x200); // Still waiting, key 3 was released for the frame
+	assert!(emulator.registers()[0x2] == 0);
+
+	keys[0x7] = true;
+	emulator.step_frame_with_input(keys).unwrap();
+	assert!(emulator.registers()[0x1] == 0x7);
+	assert!(emulator.registers()[0x2] > 0);
+	assert!(emulator.input.keys_bitmask() == 1 << 0x3); // Held keys restored
+}
+
+#[test]
+fn test_emulator_initial_state()
+{
+	// ADD V5, V6 ; LD I, 0x300 ; LD V3, [I] ; JP 0x206
+	let rom = Rom::from_bytes(&[0x85, 0x64, 0xA3, 0x00, 0xF3, 0x65, 0x12, 0x06], "preset".to_string());
+	let mut emulator = Emulator::new(&rom, EmulatorConfig::default()).unwrap();
+
+	let mut v = [0;16];
+	v[0x5] = 0x30;
+	v[0x6] = 0x12;
+	emulator.with_initial_registers(v);
+	assert!(emulator.preload_memory(&[0xAA, 0xBB, 0xCC, 0xDD], 0x300) == 4);
+	assert!(emulator.registers() == v);
+
+	emulator.step_frame().unwrap();
+	let registers = emulator.registers();
+	assert!(registers[0x5] == 0x42);
+	assert!(registers[0x0..0x4] == [0xAA, 0xBB, 0xCC, 0xDD]);
+}
+
+#[test]
+fn test_emulator_turbo()
+{
+	// ADD V0, 1 ; JP 0x200
+	let rom = Rom::from_bytes(&[0x70, 0x01, 0x12, 0x00], "count".to_string());
+	let config = EmulatorConfig { cycles_per_frame: 10, turbo_factor: 4, ..EmulatorConfig::default() };
+	let mut emulator = Emulator::new(&rom, config).unwrap();
+
+	emulator.step_frame().unwrap();
+	assert!(emulator.registers()[0x0] == 5);
+
+	emulator.set_turbo(true);
+	emulator.step_frame().unwrap();
+	assert!(emulator.registers()[0x0] == 5 + 20);
+
+	emulator.set_turbo(false);
+	emulator.step_frame().unwrap();
+	assert!(emulator.registers()[0x0] == 5 + 20 + 5);
+}
+
+#[test]
+fn test_emulator_records_audio_at_zero_timer_hz()
+{
+	use std::env;
+	use std::fs;
+
+	let rom = Rom::from_bytes(&[0x12, 0x00], "loop".to_string());
+	let config = EmulatorConfig { timer_hz: 0, ..EmulatorConfig::default() };
+	let mut emulator = Emulator::new(&rom, config).unwrap();
+	let path = env::temp_dir().join(format!("chit8-zero-hz-{}.wav", ::std::process::id()));
+	emulator.record_audio(&path).unwrap();
+
+	emulator.step_frame().unwrap();
+	let wav = fs::read(&path).unwrap();
+	fs::remove_file(&path).unwrap();
+	assert!(wav.len() == 44 + 2 * SAMPLE_RATE as usize); // A whole second per frame at 1 Hz
+}
+
+#[test]
+fn test_emulator_take_trace()
+{
+	// LD V0, 0xFF ; ADD V0, 1 ; JP 0x204
+	let rom = Rom::from_bytes(&[0x60, 0xFF, 0x70, 0x01, 0x12, 0x04], "wrap".to_string());
+	let config = EmulatorConfig { strict: true, ..EmulatorConfig::default() };
+	let mut emulator = Emulator::new(&rom, config).unwrap();
+
+	emulator.step_frame().unwrap();
+	assert!(emulator.take_trace() == vec!["0x202: warning: ADD V0, 0x1 wrapped V0 = 0xFF"]);
+
+	emulator.step_frame().unwrap();
+	assert!(emulator.take_trace().is_empty());
+}
+
+#[test]
+fn test_emulator_blank_on_exit()
+{
+	// LD F, V0 ; DRW V0, V0, 5 ; EXIT
+	let rom = Rom::from_bytes(&[0xF0, 0x29, 0xD0, 0x05, 0x00, 0xFD], "exit".to_string());
+	let mut config = EmulatorConfig::default();
+	config.quirks.platform = Platform::SuperChip;
+
+	let mut emulator = Emulator::new(&rom, config).unwrap();
+	emulator.step_frame().unwrap();
+	assert!(emulator.framebuffer().iter().any(|&on| on)); // Digit 0 left on screen
+
+	config.blank_on_exit = true;
+	let mut emulator = Emulator::new(&rom, config).unwrap();
+	emulator.step_frame().unwrap();
+	assert!(emulator.framebuffer().iter().all(|&on| !on));
+}
+
+#[test]
+fn test_emulator_xo_chip_extended_memory()
+{
+	// LD V0, 0x42 ; LD I, LONG 0x1200 ; LD [I], V0 ; LD I, 0x200 ; LD V0, [I] ; JP 0x20C
+	let rom = Rom::from_bytes(&[0x60, 0x42, 0xF0, 0x00, 0x12, 0x00, 0xF0, 0x55, 0xA2, 0x00, 0xF0, 0x65, 0x12, 0x0C], "long".to_string());
+	let mut config = EmulatorConfig::default();
+	config.quirks.platform = Platform::XoChip;
+
+	let mut emulator = Emulator::new(&rom, config).unwrap();
+	emulator.step_frame().unwrap();
+	assert!(emulator.registers()[0] == 0x60); // 0x1200 did not wrap onto the program
+
+	emulator.reload(&rom).unwrap();
+	emulator.step_frame().unwrap();
+	assert!(emulator.registers()[0] == 0x60);
+}
@@ -4,6 +4,13 @@
 //! expects. 
 
 use rand::{thread_rng, Rng};
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// `Input` -trait defines the input device the CHIP-8 emulation core expects.
 /// It consists of reading key states.
@@ -11,19 +18,98 @@ pub trait Input {
 	/// Returns an array of key states. Currently pressed keys have true as value,
 	/// other keys have false.
 	fn get_key_states(&self) -> [bool;16]; 
+
+	/// Returns true if $key was pressed between the two latest polls of the key states.
+	/// Devices that do not track key edges always return false.
+	fn just_pressed(&self, _key: u8) -> bool { false }
+
+	/// Returns true if $key was released between the two latest polls of the key states.
+	/// Devices that do not track key edges always return false.
+	fn just_released(&self, _key: u8) -> bool { false }
+
+	/// Returns true while the host's turbo key, such as Tab, is held to fast-forward the
+	/// emulation by `EmulatorConfig::turbo_factor`. Devices without one always return false.
+	fn turbo_held(&self) -> bool { false }
+
+	/// Returns the key states as a bitmask, bit n set when key n is pressed. Polls the
+	/// key states like `get_key_states`.
+	fn keys_bitmask(&self) -> u16
+	{
+		keys_to_bitmask(self.get_key_states())
+	}
+
+	/// Returns the currently pressed keys in ascending order.
+	fn pressed_keys(&self) -> impl Iterator<Item = u8> where Self: Sized
+	{
+		let keys = self.get_key_states();
+		(0..16).filter(move |&key| keys[key as usize])
+	}
+}
+
+/// Pack key states into a bitmask, bit n set when key n is pressed.
+pub fn keys_to_bitmask(keys: [bool;16]) -> u16
+{
+	keys.iter().enumerate().filter(|&(_, &pressed)| pressed).fold(0, |mask, (key, _)| mask | 1 << key)
+}
+
+/// Unpack a bitmask made by `keys_to_bitmask` into key states.
+pub fn keys_from_bitmask(mask: u16) -> [bool;16]
+{
+	let mut keys = [false;16];
+	for (key, pressed) in keys.iter_mut().enumerate() { *pressed = mask & (1 << key) != 0; }
+	keys
+}
+
+/// Tracks the key states of the two latest polls to detect pressed and released keys.
+pub struct KeyEdges {
+	previous: Cell<[bool;16]>,
+	current: Cell<[bool;16]>
+}
+
+impl KeyEdges
+{
+	pub fn new() -> KeyEdges
+	{
+		KeyEdges { previous: Cell::new([false;16]), current: Cell::new([false;16]) }
+	}
+
+	/// Record the key states of a new poll.
+	pub fn update(&self, keys: [bool;16])
+	{
+		self.previous.set(self.current.get());
+		self.current.set(keys);
+	}
+
+	/// Returns true if $key went down between the two latest polls.
+	pub fn just_pressed(&self, key: u8) -> bool
+	{
+		self.current.get()[key as usize & 0xF] && !self.previous.get()[key as usize & 0xF]
+	}
+
+	/// Returns true if $key went up between the two latest polls.
+	pub fn just_released(&self, key: u8) -> bool
+	{
+		!self.current.get()[key as usize & 0xF] && self.previous.get()[key as usize & 0xF]
+	}
+}
+
+impl Default for KeyEdges
+{
+	fn default() -> KeyEdges { KeyEdges::new() }
 }
 
 /// Emulated keyboard for the CHIP-8. Contains keys 0 to F in a numpad-like pattern.
 #[allow(dead_code)]
 pub struct Keyboard {
-	keys: [bool;16]
+	keys: [bool;16],
+	edges: KeyEdges
 }
 
 impl Keyboard
 {
 	pub fn new() -> Keyboard
 	{
-		Keyboard { keys: [false;16] }
+		Keyboard { keys: [false;16], edges: KeyEdges::new() }
 	}
 }
 
@@ -36,6 +122,642 @@ impl Input for Keyboard
 		{
 			keys[i] = thread_rng().gen();
 		}
+		self.edges.update(keys);
 		keys
 	}
-}
\ No newline at end of file
+
+	fn just_pressed(&self, key: u8) -> bool { self.edges.just_pressed(key) }
+
+	fn just_released(&self, key: u8) -> bool { self.edges.just_released(key) }
+}
+
+/// Input device without any keys. No key is ever pressed.
+pub struct NullInput;
+
+impl Input for NullInput
+{
+	fn get_key_states(&self) -> [bool;16] { [false;16] }
+}
+
+/// Input device whose key states are set directly by the owner. Convenient for
+/// front-ends and examples that track the key states themselves.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ArrayInput {
+	keys: [bool;16],
+	turbo: bool
+}
+
+impl ArrayInput
+{
+	pub fn new() -> ArrayInput
+	{
+		ArrayInput { keys: [false;16], turbo: false }
+	}
+
+	/// Press (true) or release (false) $key.
+	pub fn set(&mut self, key: u8, pressed: bool)
+	{
+		self.keys[key as usize & 0xF] = pressed;
+	}
+
+	/// Release every key.
+	pub fn clear(&mut self)
+	{
+		self.keys = [false;16];
+	}
+
+	/// Press $key and release every other key.
+	pub fn press_only(&mut self, key: u8)
+	{
+		self.clear();
+		self.set(key, true);
+	}
+
+	/// Set every key at once from $mask, bit n pressing key n.
+	pub fn set_keys_bitmask(&mut self, mask: u16)
+	{
+		self.keys = keys_from_bitmask(mask);
+	}
+
+	/// Hold (true) or release (false) the turbo key, see `Input::turbo_held`.
+	pub fn set_turbo(&mut self, held: bool)
+	{
+		self.turbo = held;
+	}
+}
+
+impl Input for ArrayInput
+{
+	fn get_key_states(&self) -> [bool;16] { self.keys }
+
+	fn turbo_held(&self) -> bool { self.turbo }
+}
+
+/// Input device driven by key events from a windowing library such as SDL. Key down
+/// events generated by keyboard auto-repeat are ignored, so a held key stays pressed
+/// for `SKP`/`SKNP` while `just_pressed` only fires once for it.
+pub struct EventInput {
+	keys: [bool;16],
+	edges: KeyEdges,
+	turbo: bool
+}
+
+impl EventInput
+{
+	pub fn new() -> EventInput
+	{
+		EventInput { keys: [false;16], edges: KeyEdges::new(), turbo: false }
+	}
+
+	/// Handle a key down event for $key. $repeat marks events generated by auto-repeat
+	/// while the key is held, which do not press the key again.
+	pub fn key_down(&mut self, key: u8, repeat: bool)
+	{
+		if repeat { return; }
+		self.keys[key as usize & 0xF] = true;
+	}
+
+	/// Handle a key up event for $key.
+	pub fn key_up(&mut self, key: u8)
+	{
+		self.keys[key as usize & 0xF] = false;
+	}
+
+	/// Handle a key down (true) or key up (false) event for the host's turbo key, such
+	/// as Tab.
+	pub fn set_turbo(&mut self, held: bool)
+	{
+		self.turbo = held;
+	}
+}
+
+impl Default for EventInput
+{
+	fn default() -> EventInput { EventInput::new() }
+}
+
+impl Input for EventInput
+{
+	fn get_key_states(&self) -> [bool;16]
+	{
+		self.edges.update(self.keys);
+		self.keys
+	}
+
+	fn just_pressed(&self, key: u8) -> bool { self.edges.just_pressed(key) }
+
+	fn just_released(&self, key: u8) -> bool { self.edges.just_released(key) }
+
+	fn turbo_held(&self) -> bool { self.turbo }
+}
+
+/// Input device that replays a prepared sequence of key states, one entry per frame.
+/// Once the script runs out the last entry stays in effect. Useful for tests and replays.
+pub struct ScriptedInput {
+	frames: Vec<[bool;16]>,
+	frame: Cell<usize>
+}
+
+impl ScriptedInput
+{
+	pub fn new(frames: Vec<[bool;16]>) -> ScriptedInput
+	{
+		ScriptedInput { frames, frame: Cell::new(0) }
+	}
+
+	/// Move on to the key states of the next frame.
+	pub fn next_frame(&self)
+	{
+		self.frame.set(self.frame.get() + 1);
+	}
+}
+
+impl Input for ScriptedInput
+{
+	fn get_key_states(&self) -> [bool;16]
+	{
+		if self.frames.is_empty() { return [false; 16]; }
+		self.frames[self.frame.get().min(self.frames.len() - 1)]
+	}
+}
+
+/// Input device receiving key states over a channel, for example from a UI thread.
+/// The latest received key states stay in effect until new ones arrive.
+pub struct ChannelInput {
+	keys: Receiver<[bool;16]>,
+	current: Cell<[bool;16]>
+}
+
+impl ChannelInput
+{
+	pub fn new(keys: Receiver<[bool;16]>) -> ChannelInput
+	{
+		ChannelInput { keys, current: Cell::new([false;16]) }
+	}
+}
+
+impl Input for ChannelInput
+{
+	fn get_key_states(&self) -> [bool;16]
+	{
+		while let Ok(keys) = self.keys.try_recv() { self.current.set(keys); }
+		self.current.get()
+	}
+}
+
+/// Map a typed character to a CHIP-8 key. Uses the common layout where the left side
+/// of a QWERTY keyboard stands in for the CHIP-8 keypad:
+///
+/// ```text
+/// 1 2 3 4      1 2 3 C
+/// q w e r  ->  4 5 6 D
+/// a s d f      7 8 9 E
+/// z x c v      A 0 B F
+/// ```
+pub fn key_for_char(c: char) -> Option<u8>
+{
+	match c.to_ascii_lowercase() {
+		'1' => Some(0x1), '2' => Some(0x2), '3' => Some(0x3), '4' => Some(0xC),
+		'q' => Some(0x4), 'w' => Some(0x5), 'e' => Some(0x6), 'r' => Some(0xD),
+		'a' => Some(0x7), 's' => Some(0x8), 'd' => Some(0x9), 'f' => Some(0xE),
+		'z' => Some(0xA), 'x' => Some(0x0), 'c' => Some(0xB), 'v' => Some(0xF),
+		_ => None
+	}
+}
+
+/// Errors validating a `KeyMap`
+#[derive(Debug, PartialEq)]
+pub enum KeyMapError {
+	/// A binding names a CHIP-8 key above 0xF
+	InvalidKey { host: char, key: u8 },
+	/// The CHIP-8 key has no host key bound to it
+	Unbound { key: u8 },
+	/// Two CHIP-8 keys are bound to the same host key, so one of them is unreachable
+	Conflict { host: char, first: u8, second: u8 },
+	/// The CHIP-8 key is bound a second time, which would drop the first host key
+	Rebound { key: u8, first: char, second: char }
+}
+
+impl fmt::Display for KeyMapError
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		match *self {
+			KeyMapError::InvalidKey { host, key } => write!(f, "'{}' is bound to 0x{:X}, which is not a CHIP-8 key", host, key),
+			KeyMapError::Unbound { key } => write!(f, "CHIP-8 key 0x{:X} is not bound to any key", key),
+			KeyMapError::Conflict { host, first, second } => write!(f, "'{}' is bound to both CHIP-8 keys 0x{:X} and 0x{:X}", host, first, second),
+			KeyMapError::Rebound { key, first, second } => write!(f, "CHIP-8 key 0x{:X} is bound to both '{}' and '{}'", key, first, second)
+		}
+	}
+}
+
+impl Error for KeyMapError {}
+
+/// Mapping from typed characters to the 16 CHIP-8 keys. Every CHIP-8 key is bound to
+/// exactly one character. The default is the layout of `key_for_char`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyMap {
+	/// Character bound to each CHIP-8 key
+	hosts: [char;16]
+}
+
+impl KeyMap
+{
+	/// Create a key map from (character, CHIP-8 key) bindings. Fails if a binding names
+	/// a key above 0xF, a key is left unbound or bound twice, or a character is bound
+	/// to two keys.
+	/// Characters are matched case-insensitively.
+	pub fn new(bindings: &[(char, u8)]) -> Result<KeyMap, KeyMapError>
+	{
+		let mut hosts: [Option<char>;16] = [None;16];
+
+		for &(host, key) in bindings
+		{
+			if key > 0xF { return Err(KeyMapError::InvalidKey { host, key }); }
+
+			let host = host.to_ascii_lowercase();
+			if let Some(first) = hosts[key as usize]
+			{
+				return Err(KeyMapError::Rebound { key, first, second: host });
+			}
+			if let Some(first) = (0..16).find(|&k| hosts[k as usize] == Some(host))
+			{
+				return Err(KeyMapError::Conflict { host, first, second: key });
+			}
+			hosts[key as usize] = Some(host);
+		}
+
+		let mut map = KeyMap { hosts: ['\0';16] };
+		for (key, host) in hosts.iter().enumerate()
+		{
+			match *host {
+				Some(host) => map.hosts[key] = host,
+				None => return Err(KeyMapError::Unbound { key: key as u8 })
+			}
+		}
+		Ok(map)
+	}
+
+	/// CHIP-8 key bound to the character $c, if any.
+	pub fn key_for(&self, c: char) -> Option<u8>
+	{
+		let c = c.to_ascii_lowercase();
+		self.hosts.iter().position(|&host| host == c).map(|key| key as u8)
+	}
+}
+
+impl Default for KeyMap
+{
+	fn default() -> KeyMap
+	{
+		let bindings: Vec<(char, u8)> = "1234qwerasdfzxcv".chars().map(|c| (c, key_for_char(c).unwrap())).collect();
+		KeyMap::new(&bindings).unwrap()
+	}
+}
+
+/// Input device reading typed characters from the standard input without blocking.
+/// Keys are mapped with a `KeyMap`, by default the layout of `key_for_char`.
+///
+/// Terminals only report key presses, so a key counts as held for a short window
+/// after each typed character. The terminal should be in raw mode for characters to
+/// arrive as they are typed instead of a line at a time.
+pub struct StdinInput {
+	chars: Receiver<u8>,
+	pressed_at: RefCell<[Option<Instant>;16]>,
+	/// When Tab, the turbo key, was last typed
+	turbo_at: Cell<Option<Instant>>,
+	hold: Duration,
+	key_map: KeyMap
+}
+
+impl StdinInput
+{
+	/// Start reading the standard input on a background thread. Keys stay held for
+	/// $hold after each typed character. Tab holds the turbo key the same way.
+	pub fn new(hold: Duration) -> StdinInput
+	{
+		StdinInput::with_key_map(hold, KeyMap::default())
+	}
+
+	/// Start reading the standard input like `new`, mapping typed characters to keys
+	/// with $key_map.
+	pub fn with_key_map(hold: Duration, key_map: KeyMap) -> StdinInput
+	{
+		let (sender, receiver) = mpsc::channel();
+		thread::spawn(move || {
+			let mut stdin = io::stdin();
+			let mut buffer = [0u8; 16];
+			loop {
+				let length = match stdin.read(&mut buffer) { Ok(0) | Err(_) => return, Ok(l) => l };
+				for byte in &buffer[..length]
+				{
+					if sender.send(*byte).is_err() { return; }
+				}
+			}
+		});
+
+		StdinInput::from_receiver(receiver, hold, key_map)
+	}
+
+	/// Read typed characters from $chars instead of the standard input.
+	fn from_receiver(chars: Receiver<u8>, hold: Duration, key_map: KeyMap) -> StdinInput
+	{
+		StdinInput { chars, pressed_at: RefCell::new([None;16]), turbo_at: Cell::new(None), hold, key_map }
+	}
+
+	/// Press the keys of the characters typed since the last poll at $now.
+	fn poll(&self, now: Instant)
+	{
+		let mut pressed_at = self.pressed_at.borrow_mut();
+		while let Ok(byte) = self.chars.try_recv()
+		{
+			if byte == b'\t' { self.turbo_at.set(Some(now)); }
+			if let Some(key) = self.key_map.key_for(byte as char) { pressed_at[key as usize] = Some(now); }
+		}
+	}
+
+	/// Whether something typed at $at is still held at $now.
+	fn held(&self, at: Option<Instant>, now: Instant) -> bool
+	{
+		match at { Some(at) => now.duration_since(at) < self.hold, None => false }
+	}
+}
+
+impl Input for StdinInput
+{
+	fn get_key_states(&self) -> [bool;16]
+	{
+		let now = Instant::now();
+		self.poll(now);
+
+		let mut keys = [false;16];
+		for (key, at) in self.pressed_at.borrow().iter().enumerate()
+		{
+			keys[key] = self.held(*at, now);
+		}
+		keys
+	}
+
+	fn turbo_held(&self) -> bool
+	{
+		let now = Instant::now();
+		self.poll(now);
+		self.held(self.turbo_at.get(), now)
+	}
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[test]
+fn test_scripted_input()
+{
+	let mut pressed = [false; 16];
+	pressed[0x5] = true;
+	let input = ScriptedInput::new(vec![[false; 16], pressed]);
+
+	assert!(input.get_key_states() == [false; 16]);
+	input.next_frame();
+	assert!(input.get_key_states() == pressed);
+	input.next_frame();
+	assert!(input.get_key_states() == pressed); // Last frame repeats
+
+	assert!(ScriptedInput::new(Vec::new()).get_key_states() == [false; 16]);
+}
+
+#[test]
+fn test_key_for_char()
+{
+	let layout = "1234qwerasdfzxcv";
+	let keys = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+	for (c, key) in layout.chars().zip(keys.iter())
+	{
+		assert!(key_for_char(c) == Some(*key));
+	}
+
+	assert!(key_for_char('Q') == Some(0x4)); // Case insensitive
+	assert!(key_for_char('5').is_none());
+	assert!(key_for_char(' ').is_none());
+}
+
+/// Input whose key states come from a script and which tracks edges like `Keyboard`.
+#[cfg(test)]
+struct StatefulMockInput {
+	script: ScriptedInput,
+	edges: KeyEdges
+}
+
+#[cfg(test)]
+impl Input for StatefulMockInput
+{
+	fn get_key_states(&self) -> [bool;16]
+	{
+		let keys = self.script.get_key_states();
+		self.script.next_frame();
+		self.edges.update(keys);
+		keys
+	}
+
+	fn just_pressed(&self, key: u8) -> bool { self.edges.just_pressed(key) }
+
+	fn just_released(&self, key: u8) -> bool { self.edges.just_released(key) }
+}
+
+#[test]
+fn test_key_edges()
+{
+	let mut down = [false;16];
+	down[0x7] = true;
+	let input = StatefulMockInput { script: ScriptedInput::new(vec![[false;16], down, down, down, [false;16], [false;16]]), edges: KeyEdges::new() };
+
+	let mut pressed = 0;
+	let mut released = 0;
+	for _ in 0..6
+	{
+		input.get_key_states();
+		if input.just_pressed(0x7) { pressed += 1; }
+		if input.just_released(0x7) { released += 1; }
+		assert!(!input.just_pressed(0x6) && !input.just_released(0x6));
+	}
+
+	assert!(pressed == 1); // Held key only fires once
+	assert!(released == 1);
+}
+
+#[test]
+fn test_edges_default_to_false()
+{
+	let mut down = [false;16];
+	down[0x1] = true;
+	let input = ScriptedInput::new(vec![down]);
+
+	input.get_key_states();
+	assert!(!input.just_pressed(0x1));
+	assert!(!input.just_released(0x1));
+}
+
+#[test]
+fn test_channel_input()
+{
+	let (sender, receiver) = mpsc::channel();
+	let input = ChannelInput::new(receiver);
+	assert!(input.get_key_states() == [false;16]);
+
+	let mut first = [false;16];
+	first[0x1] = true;
+	let mut second = [false;16];
+	second[0x2] = true;
+	sender.send(first).unwrap();
+	sender.send(second).unwrap();
+	assert!(input.get_key_states() == second); // Latest state wins
+
+	drop(sender);
+	assert!(input.get_key_states() == second);
+}
+
+#[test]
+fn test_array_input()
+{
+	let mut input = ArrayInput::new();
+	assert!(input.get_key_states() == [false;16]);
+
+	input.set(0x3, true);
+	input.set(0xA, true);
+	assert!(input.get_key_states()[0x3] && input.get_key_states()[0xA]);
+
+	input.set(0x3, false);
+	assert!(!input.get_key_states()[0x3] && input.get_key_states()[0xA]);
+
+	input.press_only(0x5);
+	let mut expected = [false;16];
+	expected[0x5] = true;
+	assert!(input.get_key_states() == expected);
+
+	input.clear();
+	assert!(input.get_key_states() == [false;16]);
+}
+
+#[test]
+fn test_turbo_held()
+{
+	let mut array = ArrayInput::new();
+	assert!(!array.turbo_held());
+	array.set_turbo(true);
+	assert!(array.turbo_held() && array.get_key_states() == [false;16]);
+
+	let mut events = EventInput::new();
+	events.set_turbo(true);
+	assert!(events.turbo_held());
+	events.set_turbo(false);
+	assert!(!events.turbo_held());
+
+	let (sender, receiver) = mpsc::channel();
+	let stdin = StdinInput::from_receiver(receiver, Duration::from_secs(60), KeyMap::default());
+	assert!(!stdin.turbo_held());
+	sender.send(b'\t').unwrap();
+	assert!(stdin.turbo_held());
+	assert!(stdin.get_key_states() == [false;16]); // Tab is not a CHIP-8 key
+
+	assert!(!NullInput.turbo_held());
+}
+
+#[test]
+fn test_array_input_bitmask()
+{
+	let mut input = ArrayInput::new();
+	assert!(input.keys_bitmask() == 0);
+
+	input.set_keys_bitmask(0x8421);
+	assert!(input.keys_bitmask() == 0x8421);
+	for (key, &pressed) in input.get_key_states().iter().enumerate() { assert!(pressed == (key % 5 == 0)); } // Keys 0, 5, A and F
+
+	input.set(0x1, true);
+	assert!(input.keys_bitmask() == 0x8423);
+	assert!(keys_from_bitmask(0xFFFF) == [true;16]);
+	assert!(keys_to_bitmask(keys_from_bitmask(0x1234)) == 0x1234);
+}
+
+#[test]
+fn test_keys_bitmask_of_any_input()
+{
+	let mut events = EventInput::new();
+	events.key_down(0x2, false);
+	events.key_down(0xC, false);
+	assert!(events.keys_bitmask() == 0x1004);
+
+	let script = ScriptedInput::new(vec![keys_from_bitmask(0x0080)]);
+	assert!(script.keys_bitmask() == 0x0080);
+	assert!(NullInput.keys_bitmask() == 0);
+}
+
+#[test]
+fn test_event_input_ignores_repeats()
+{
+	let mut input = EventInput::new();
+	let mut pressed = 0;
+
+	input.key_down(0x5, false);
+	for _ in 0..4
+	{
+		input.get_key_states();
+		if input.just_pressed(0x5) { pressed += 1; }
+		input.key_down(0x5, true); // Auto-repeat while held
+	}
+	assert!(pressed == 1);
+	assert!(input.get_key_states()[0x5]);
+
+	input.key_up(0x5);
+	assert!(!input.get_key_states()[0x5]);
+	assert!(input.just_released(0x5));
+
+	input.key_down(0x5, true); // Stray repeat after release
+	assert!(!input.get_key_states()[0x5]);
+}
+
+#[test]
+fn test_key_map()
+{
+	let map = KeyMap::default();
+	for c in "1234qwerasdfzxcvQ".chars() { assert!(map.key_for(c) == key_for_char(c)); }
+	assert!(map.key_for('p').is_none());
+
+	// Keypad laid out on the numpad, with the remaining keys on letters
+	let bindings: Vec<(char, u8)> = "0123456789abcdef".chars().map(|c| (c, c.to_digit(16).unwrap() as u8)).collect();
+	let map = KeyMap::new(&bindings).unwrap();
+	assert!(map.key_for('7') == Some(0x7));
+	assert!(map.key_for('F') == Some(0xF));
+}
+
+#[test]
+fn test_stdin_input_key_map()
+{
+	let bindings: Vec<(char, u8)> = "0123456789abcdef".chars().map(|c| (c, c.to_digit(16).unwrap() as u8)).collect();
+	let (sender, receiver) = mpsc::channel();
+	let stdin = StdinInput::from_receiver(receiver, Duration::from_secs(60), KeyMap::new(&bindings).unwrap());
+
+	sender.send(b'7').unwrap();
+	sender.send(b'q').unwrap(); // Key 4 in the default layout, unbound here
+	assert!(stdin.keys_bitmask() == 1 << 0x7);
+}
+
+#[test]
+fn test_key_map_errors()
+{
+	let mut bindings: Vec<(char, u8)> = "0123456789abcdef".chars().map(|c| (c, c.to_digit(16).unwrap() as u8)).collect();
+
+	bindings[0xB] = ('A', 0xB);
+	assert!(KeyMap::new(&bindings) == Err(KeyMapError::Conflict { host: 'a', first: 0xA, second: 0xB }));
+
+	bindings[0xB] = ('b', 0xB);
+	assert!(KeyMap::new(&bindings[..0xB]) == Err(KeyMapError::Unbound { key: 0xB }));
+
+	bindings.push(('g', 0x1));
+	assert!(KeyMap::new(&bindings) == Err(KeyMapError::Rebound { key: 0x1, first: '1', second: 'g' }));
+	assert!(KeyMap::new(&[('1', 0x1), ('2', 0x1)]).unwrap_err().to_string() == "CHIP-8 key 0x1 is bound to both '1' and '2'");
+
+	bindings.pop();
+	bindings.push(('g', 0x10));
+	assert!(KeyMap::new(&bindings) == Err(KeyMapError::InvalidKey { host: 'g', key: 0x10 }));
+}
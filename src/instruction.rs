@@ -0,0 +1,762 @@
+//! Typed representation of the CHIP-8 opcodes.
+//!
+//! `decode` turns an opcode into an `Instruction` using the same `decode_opcode!`
+//! macro as the CPU and the disassembler. The `Instruction` helpers categorize
+//! instructions for control flow analysis and other tooling, and write them out
+//! as assembly in either Cowgod's or Octo's syntax.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use rom::Rom;
+
+/// A decoded CHIP-8 instruction. Register parameters are register indices (0x0 to 0xF),
+/// address parameters are 12-bit addresses. The descriptions of the instructions are
+/// sourced from http://devernay.free.fr/hacks/chip8/C8TECH10.HTM and modified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction {
+	/// 00E0 - CLS
+	/// Clear the display.
+	Cls,
+	/// 00EE - RET
+	/// Return from a subroutine: set the program counter to the address at the top of the stack.
+	Ret,
+	/// 00FD - EXIT (SuperCHIP)
+	/// Exit the interpreter.
+	Exit,
+	/// 0nnn - SYS addr
+	/// Jump to a machine code routine at addr. Commonly ignored.
+	Sys(u16),
+	/// 1nnn - JP addr
+	/// Jump to location addr.
+	Jp(u16),
+	/// 2nnn - CALL addr
+	/// Call subroutine at addr: put the current program counter on the stack, then jump to addr.
+	Call(u16),
+	/// 3xkk - SE Vx, byte
+	/// Skip next instruction if Vx == byte.
+	Se(u8, u8),
+	/// 4xkk - SNE Vx, byte
+	/// Skip next instruction if Vx != byte.
+	Sne(u8, u8),
+	/// 5xy0 - SE Vx, Vy
+	/// Skip next instruction if Vx == Vy.
+	SeReg(u8, u8),
+	/// 6xkk - LD Vx, byte
+	/// Set Vx = byte.
+	Ldx(u8, u8),
+	/// 7xkk - ADD Vx, byte
+	/// Set Vx = Vx + byte.
+	AddByte(u8, u8),
+	/// 8xy0 - LD Vx, Vy
+	/// Set Vx = Vy.
+	Ld(u8, u8),
+	/// 8xy1 - OR Vx, Vy
+	/// Set Vx = Vx OR Vy.
+	Or(u8, u8),
+	/// 8xy2 - AND Vx, Vy
+	/// Set Vx = Vx AND Vy.
+	And(u8, u8),
+	/// 8xy3 - XOR Vx, Vy
+	/// Set Vx = Vx XOR Vy.
+	Xor(u8, u8),
+	/// 8xy4 - ADD Vx, Vy
+	/// Set Vx = Vx + Vy, set VF = carry.
+	AddReg(u8, u8),
+	/// 8xy5 - SUB Vx, Vy
+	/// Set Vx = Vx - Vy, set VF = NOT borrow.
+	Sub(u8, u8),
+	/// 8xy6 - SHR Vx {, Vy}
+	/// Set Vx = Vx SHR 1, set VF = the shifted out least-significant bit.
+	Shr(u8, u8),
+	/// 8xy7 - SUBN Vx, Vy
+	/// Set Vx = Vy - Vx, set VF = NOT borrow.
+	Subn(u8, u8),
+	/// 8xyE - SHL Vx {, Vy}
+	/// Set Vx = Vx SHL 1, set VF = the shifted out most-significant bit.
+	Shl(u8, u8),
+	/// 9xy0 - SNE Vx, Vy
+	/// Skip next instruction if Vx != Vy.
+	SneReg(u8, u8),
+	/// Annn - LD I, addr
+	/// Set I = addr.
+	Ldi(u16),
+	/// Bnnn - JP V0, addr
+	/// Jump to location addr + V0.
+	JpV0(u16),
+	/// Cxkk - RND Vx, byte
+	/// Set Vx = random byte AND byte.
+	Rnd(u8, u8),
+	/// Dxyn - DRW Vx, Vy, nibble
+	/// Display the nibble-byte sprite at memory location I at (Vx, Vy), set VF = collision.
+	Drw(u8, u8, u8),
+	/// Ex9E - SKP Vx
+	/// Skip next instruction if the key with the value of Vx is pressed.
+	Skp(u8),
+	/// ExA1 - SKNP Vx
+	/// Skip next instruction if the key with the value of Vx is not pressed.
+	Sknp(u8),
+	/// Fx07 - LD Vx, DT
+	/// Set Vx = delay timer value.
+	LdDtIntoVx(u8),
+	/// Fx0A - LD Vx, K
+	/// Wait for a key press, store the value of the key in Vx.
+	LdKIntoVx(u8),
+	/// Fx15 - LD DT, Vx
+	/// Set delay timer = Vx.
+	LdVxIntoDt(u8),
+	/// Fx18 - LD ST, Vx
+	/// Set sound timer = Vx.
+	LdVxIntoSt(u8),
+	/// Fx1E - ADD I, Vx
+	/// Set I = I + Vx.
+	AddVx(u8),
+	/// Fx29 - LD F, Vx
+	/// Set I = location of the sprite for the hexadecimal digit Vx.
+	LdVxDigitIntoF(u8),
+	/// Fx33 - LD B, Vx
+	/// Store the BCD representation of Vx in memory locations I, I+1 and I+2.
+	LdVxIntoBcd(u8),
+	/// Fx55 - LD [I], Vx
+	/// Store registers V0 through Vx in memory starting at location I.
+	LdV0ToVxIntoI(u8),
+	/// Fx65 - LD Vx, [I]
+	/// Read registers V0 through Vx from memory starting at location I.
+	LdIIntoV0ToVx(u8),
+	/// Fn01 - PLANE n (XO-CHIP)
+	/// Select the bit-planes n that drawing and clearing affect.
+	Plane(u8),
+	/// F000 nnnn - LD I, LONG nnnn (XO-CHIP). The address follows in the next word.
+	/// Set I = the 16-bit address in the following word.
+	LdILong,
+	/// Opcode that does not decode to any instruction
+	Unknown(u16)
+}
+
+impl Instruction {
+	/// Length of the instruction in bytes.
+	pub fn length(&self) -> u16
+	{
+		match *self {
+			Instruction::LdILong => 4,
+			_ => 2
+		}
+	}
+
+	/// Approximate number of COSMAC VIP machine cycles the instruction takes, for
+	/// timing closer to the original hardware. The figures are rough averages: on
+	/// the VIP the cost of some instructions also depends on their operands and on
+	/// whether a skip is taken.
+	pub fn cycles(&self) -> u32
+	{
+		match *self {
+			Instruction::Cls => 24,
+			Instruction::Ret | Instruction::Exit | Instruction::Sys(_) => 10,
+			Instruction::Jp(_) | Instruction::Ldi(_) => 12,
+			Instruction::Call(_) => 26,
+			Instruction::Se(..) | Instruction::Sne(..) | Instruction::SeReg(..) | Instruction::SneReg(..) => 10,
+			Instruction::Ldx(..) => 6,
+			Instruction::AddByte(..) => 10,
+			Instruction::Ld(..) | Instruction::Or(..) | Instruction::And(..) | Instruction::Xor(..) |
+			Instruction::AddReg(..) | Instruction::Sub(..) | Instruction::Shr(..) | Instruction::Subn(..) |
+			Instruction::Shl(..) => 44,
+			Instruction::JpV0(_) => 22,
+			Instruction::Rnd(..) => 36,
+			Instruction::Drw(_, _, rows) => 68 + 24 * rows as u32,
+			Instruction::Skp(_) | Instruction::Sknp(_) => 14,
+			Instruction::LdDtIntoVx(_) | Instruction::LdVxIntoDt(_) | Instruction::LdVxIntoSt(_) => 10,
+			Instruction::LdKIntoVx(_) => 18,
+			Instruction::AddVx(_) => 16,
+			Instruction::LdVxDigitIntoF(_) => 20,
+			Instruction::LdVxIntoBcd(_) => 84,
+			Instruction::LdV0ToVxIntoI(x) | Instruction::LdIIntoV0ToVx(x) => 14 + 14 * (x as u32 + 1),
+			Instruction::Plane(_) => 10,
+			Instruction::LdILong => 20,
+			Instruction::Unknown(_) => 2
+		}
+	}
+
+	/// Bitmask of the V registers the instruction reads, bit n for Vn. Vy of `SHR` and
+	/// `SHL` is left out as it is only read with the `shift_uses_vy` quirk.
+	pub fn registers_read(&self) -> u16
+	{
+		match *self {
+			Instruction::Se(x, _) | Instruction::Sne(x, _) | Instruction::AddByte(x, _) |
+			Instruction::Shr(x, _) | Instruction::Shl(x, _) | Instruction::Skp(x) | Instruction::Sknp(x) |
+			Instruction::LdVxIntoDt(x) | Instruction::LdVxIntoSt(x) | Instruction::AddVx(x) |
+			Instruction::LdVxDigitIntoF(x) | Instruction::LdVxIntoBcd(x) => 1 << x,
+			Instruction::Ld(_, y) => 1 << y,
+			Instruction::SeReg(x, y) | Instruction::SneReg(x, y) | Instruction::Or(x, y) | Instruction::And(x, y) |
+			Instruction::Xor(x, y) | Instruction::AddReg(x, y) | Instruction::Sub(x, y) | Instruction::Subn(x, y) |
+			Instruction::Drw(x, y, _) => 1 << x | 1 << y,
+			Instruction::JpV0(_) => 1,
+			Instruction::LdV0ToVxIntoI(x) => registers_up_to(x),
+			_ => 0
+		}
+	}
+
+	/// Bitmask of the V registers the instruction writes, bit n for Vn.
+	pub fn registers_written(&self) -> u16
+	{
+		match *self {
+			Instruction::Ldx(x, _) | Instruction::AddByte(x, _) | Instruction::Ld(x, _) | Instruction::Rnd(x, _) |
+			Instruction::LdDtIntoVx(x) | Instruction::LdKIntoVx(x) => 1 << x,
+			Instruction::Or(x, _) | Instruction::And(x, _) | Instruction::Xor(x, _) | Instruction::AddReg(x, _) |
+			Instruction::Sub(x, _) | Instruction::Subn(x, _) | Instruction::Shr(x, _) | Instruction::Shl(x, _) => 1 << x | 1 << 0xF,
+			Instruction::Drw(..) => 1 << 0xF,
+			Instruction::LdIIntoV0ToVx(x) => registers_up_to(x),
+			_ => 0
+		}
+	}
+
+	/// Returns the opcode pattern of the instruction, such as `8xy4` for `ADD Vx, Vy`.
+	/// Identifies the kind of instruction regardless of its parameters.
+	pub fn pattern(&self) -> &'static str
+	{
+		match *self {
+			Instruction::Cls => "00E0",
+			Instruction::Ret => "00EE",
+			Instruction::Exit => "00FD",
+			Instruction::Sys(_) => "0nnn",
+			Instruction::Jp(_) => "1nnn",
+			Instruction::Call(_) => "2nnn",
+			Instruction::Se(..) => "3xkk",
+			Instruction::Sne(..) => "4xkk",
+			Instruction::SeReg(..) => "5xy0",
+			Instruction::Ldx(..) => "6xkk",
+			Instruction::AddByte(..) => "7xkk",
+			Instruction::Ld(..) => "8xy0",
+			Instruction::Or(..) => "8xy1",
+			Instruction::And(..) => "8xy2",
+			Instruction::Xor(..) => "8xy3",
+			Instruction::AddReg(..) => "8xy4",
+			Instruction::Sub(..) => "8xy5",
+			Instruction::Shr(..) => "8xy6",
+			Instruction::Subn(..) => "8xy7",
+			Instruction::Shl(..) => "8xyE",
+			Instruction::SneReg(..) => "9xy0",
+			Instruction::Ldi(_) => "Annn",
+			Instruction::JpV0(_) => "Bnnn",
+			Instruction::Rnd(..) => "Cxkk",
+			Instruction::Drw(..) => "Dxyn",
+			Instruction::Skp(_) => "Ex9E",
+			Instruction::Sknp(_) => "ExA1",
+			Instruction::LdDtIntoVx(_) => "Fx07",
+			Instruction::LdKIntoVx(_) => "Fx0A",
+			Instruction::LdVxIntoDt(_) => "Fx15",
+			Instruction::LdVxIntoSt(_) => "Fx18",
+			Instruction::AddVx(_) => "Fx1E",
+			Instruction::LdVxDigitIntoF(_) => "Fx29",
+			Instruction::LdVxIntoBcd(_) => "Fx33",
+			Instruction::LdV0ToVxIntoI(_) => "Fx55",
+			Instruction::LdIIntoV0ToVx(_) => "Fx65",
+			Instruction::Plane(_) => "Fn01",
+			Instruction::LdILong => "F000",
+			Instruction::Unknown(_) => "unknown"
+		}
+	}
+
+	/// Returns true for instructions that unconditionally transfer control elsewhere:
+	/// jumps, calls and returns.
+	pub fn is_jump(&self) -> bool
+	{
+		matches!(*self, Instruction::Jp(_) | Instruction::JpV0(_) | Instruction::Call(_) | Instruction::Ret)
+	}
+
+	/// Returns true for instructions that conditionally skip the next instruction.
+	pub fn is_branch(&self) -> bool
+	{
+		matches!(*self, Instruction::Se(..) | Instruction::Sne(..) | Instruction::SeReg(..) | Instruction::SneReg(..) |
+			Instruction::Skp(_) | Instruction::Sknp(_))
+	}
+
+	/// Encode the instruction back into its opcode, the inverse of `decode`.
+	/// `LdILong` encodes to its first word only, the address follows separately.
+	pub fn encode(&self) -> u16
+	{
+		let xy = |op: u16, x: u8, y: u8| op | (x as u16) << 8 | (y as u16) << 4;
+		let xkk = |op: u16, x: u8, kk: u8| op | (x as u16) << 8 | kk as u16;
+		let x = |op: u16, x: u8| op | (x as u16) << 8;
+
+		match *self {
+			Instruction::Cls => 0x00E0,
+			Instruction::Ret => 0x00EE,
+			Instruction::Exit => 0x00FD,
+			Instruction::Sys(addr) => addr & 0x0FFF,
+			Instruction::Jp(addr) => 0x1000 | addr & 0x0FFF,
+			Instruction::Call(addr) => 0x2000 | addr & 0x0FFF,
+			Instruction::Se(r, kk) => xkk(0x3000, r, kk),
+			Instruction::Sne(r, kk) => xkk(0x4000, r, kk),
+			Instruction::SeReg(r, s) => xy(0x5000, r, s),
+			Instruction::Ldx(r, kk) => xkk(0x6000, r, kk),
+			Instruction::AddByte(r, kk) => xkk(0x7000, r, kk),
+			Instruction::Ld(r, s) => xy(0x8000, r, s),
+			Instruction::Or(r, s) => xy(0x8001, r, s),
+			Instruction::And(r, s) => xy(0x8002, r, s),
+			Instruction::Xor(r, s) => xy(0x8003, r, s),
+			Instruction::AddReg(r, s) => xy(0x8004, r, s),
+			Instruction::Sub(r, s) => xy(0x8005, r, s),
+			Instruction::Shr(r, s) => xy(0x8006, r, s),
+			Instruction::Subn(r, s) => xy(0x8007, r, s),
+			Instruction::Shl(r, s) => xy(0x800E, r, s),
+			Instruction::SneReg(r, s) => xy(0x9000, r, s),
+			Instruction::Ldi(addr) => 0xA000 | addr & 0x0FFF,
+			Instruction::JpV0(addr) => 0xB000 | addr & 0x0FFF,
+			Instruction::Rnd(r, kk) => xkk(0xC000, r, kk),
+			Instruction::Drw(r, s, n) => xy(0xD000, r, s) | (n & 0xF) as u16,
+			Instruction::Skp(r) => x(0xE09E, r),
+			Instruction::Sknp(r) => x(0xE0A1, r),
+			Instruction::LdDtIntoVx(r) => x(0xF007, r),
+			Instruction::LdKIntoVx(r) => x(0xF00A, r),
+			Instruction::LdVxIntoDt(r) => x(0xF015, r),
+			Instruction::LdVxIntoSt(r) => x(0xF018, r),
+			Instruction::AddVx(r) => x(0xF01E, r),
+			Instruction::LdVxDigitIntoF(r) => x(0xF029, r),
+			Instruction::LdVxIntoBcd(r) => x(0xF033, r),
+			Instruction::LdV0ToVxIntoI(r) => x(0xF055, r),
+			Instruction::LdIIntoV0ToVx(r) => x(0xF065, r),
+			Instruction::Plane(mask) => x(0xF001, mask),
+			Instruction::LdILong => 0xF000,
+			Instruction::Unknown(op) => op
+		}
+	}
+
+	/// Returns the address control is transferred to, if it is known statically.
+	/// `JP V0, addr` depends on V0 and has no static target.
+	pub fn target(&self) -> Option<u16>
+	{
+		match *self {
+			Instruction::Jp(addr) | Instruction::Call(addr) => Some(addr),
+			_ => None
+		}
+	}
+}
+
+/// Assembly dialects instructions can be written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MnemonicStyle {
+	/// Mnemonics of Cowgod's technical reference, such as `LD V0, 0xA`
+	#[default]
+	Cowgod,
+	/// Syntax of the Octo assembler, such as `v0 := 0x0A`
+	Octo
+}
+
+impl Instruction {
+	/// Returns the instruction written in the given assembly dialect. `LdILong` is
+	/// written without its address, which follows the opcode in memory.
+	pub fn mnemonic(&self, style: MnemonicStyle) -> String
+	{
+		match style {
+			MnemonicStyle::Cowgod => self.to_string(),
+			MnemonicStyle::Octo => self.octo()
+		}
+	}
+
+	/// Octo syntax of the instruction. Octo expresses skips as `if .. then` with the
+	/// condition under which the next instruction runs, the opposite of the skip condition.
+	fn octo(&self) -> String
+	{
+		match *self {
+			Instruction::Cls => "clear".to_string(),
+			Instruction::Ret => "return".to_string(),
+			Instruction::Exit => "exit".to_string(),
+			Instruction::Sys(addr) => format!("native 0x{:03X}", addr),
+			Instruction::Jp(addr) => format!("jump 0x{:03X}", addr),
+			Instruction::Call(addr) => format!(":call 0x{:03X}", addr),
+			Instruction::Se(x, kk) => format!("if v{:x} != 0x{:02X} then", x, kk),
+			Instruction::Sne(x, kk) => format!("if v{:x} == 0x{:02X} then", x, kk),
+			Instruction::SeReg(x, y) => format!("if v{:x} != v{:x} then", x, y),
+			Instruction::Ldx(x, kk) => format!("v{:x} := 0x{:02X}", x, kk),
+			Instruction::AddByte(x, kk) => format!("v{:x} += 0x{:02X}", x, kk),
+			Instruction::Ld(x, y) => format!("v{:x} := v{:x}", x, y),
+			Instruction::Or(x, y) => format!("v{:x} |= v{:x}", x, y),
+			Instruction::And(x, y) => format!("v{:x} &= v{:x}", x, y),
+			Instruction::Xor(x, y) => format!("v{:x} ^= v{:x}", x, y),
+			Instruction::AddReg(x, y) => format!("v{:x} += v{:x}", x, y),
+			Instruction::Sub(x, y) => format!("v{:x} -= v{:x}", x, y),
+			Instruction::Shr(x, y) => format!("v{:x} >>= v{:x}", x, y),
+			Instruction::Subn(x, y) => format!("v{:x} =- v{:x}", x, y),
+			Instruction::Shl(x, y) => format!("v{:x} <<= v{:x}", x, y),
+			Instruction::SneReg(x, y) => format!("if v{:x} == v{:x} then", x, y),
+			Instruction::Ldi(addr) => format!("i := 0x{:03X}", addr),
+			Instruction::JpV0(addr) => format!("jump0 0x{:03X}", addr),
+			Instruction::Rnd(x, kk) => format!("v{:x} := random 0x{:02X}", x, kk),
+			Instruction::Drw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+			Instruction::Skp(x) => format!("if v{:x} -key then", x),
+			Instruction::Sknp(x) => format!("if v{:x} key then", x),
+			Instruction::LdDtIntoVx(x) => format!("v{:x} := delay", x),
+			Instruction::LdKIntoVx(x) => format!("v{:x} := key", x),
+			Instruction::LdVxIntoDt(x) => format!("delay := v{:x}", x),
+			Instruction::LdVxIntoSt(x) => format!("buzzer := v{:x}", x),
+			Instruction::AddVx(x) => format!("i += v{:x}", x),
+			Instruction::LdVxDigitIntoF(x) => format!("i := hex v{:x}", x),
+			Instruction::LdVxIntoBcd(x) => format!("bcd v{:x}", x),
+			Instruction::LdV0ToVxIntoI(x) => format!("save v{:x}", x),
+			Instruction::LdIIntoV0ToVx(x) => format!("load v{:x}", x),
+			Instruction::Plane(mask) => format!("plane {}", mask),
+			Instruction::LdILong => "i := long".to_string(),
+			Instruction::Unknown(op) => format!("0x{:02X} 0x{:02X}", op >> 8, op & 0xFF)
+		}
+	}
+}
+
+impl fmt::Display for Instruction
+{
+	/// Write the instruction with the mnemonics of Cowgod's technical reference.
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			Instruction::Cls => write!(f, "CLS"),
+			Instruction::Ret => write!(f, "RET"),
+			Instruction::Exit => write!(f, "EXIT"),
+			Instruction::Sys(addr) => write!(f, "SYS 0x{:0>4X}", addr),
+			Instruction::Jp(addr) => write!(f, "JP {:#X}", addr),
+			Instruction::Call(addr) => write!(f, "CALL {:#X}", addr),
+			Instruction::Se(x, kk) => write!(f, "SE V{:X}, {:X}", x, kk),
+			Instruction::Sne(x, kk) => write!(f, "SNE V{:X}, {:X}", x, kk),
+			Instruction::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+			Instruction::Ldx(x, kk) => write!(f, "LD V{:X}, {:#X}", x, kk),
+			Instruction::AddByte(x, kk) => write!(f, "ADD V{:X}, {:X}", x, kk),
+			Instruction::Ld(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+			Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+			Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+			Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+			Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+			Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+			Instruction::Shr(x, _) => write!(f, "SHR V{:X}", x),
+			Instruction::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+			Instruction::Shl(x, _) => write!(f, "SHL V{:X}", x),
+			Instruction::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+			Instruction::Ldi(addr) => write!(f, "LD I, {:#X}", addr),
+			Instruction::JpV0(addr) => write!(f, "JP V0, {:#X}", addr),
+			Instruction::Rnd(x, kk) => write!(f, "RND V{:X}, {:X}", x, kk),
+			Instruction::Drw(x, y, n) => write!(f, "DRW (V{:X}, V{:X}) for {:X} bytes", x, y, n),
+			Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+			Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+			Instruction::LdDtIntoVx(x) => write!(f, "LD V{:X}, DT", x),
+			Instruction::LdKIntoVx(x) => write!(f, "LD V{:X}, K", x),
+			Instruction::LdVxIntoDt(x) => write!(f, "LD DT, V{:X}", x),
+			Instruction::LdVxIntoSt(x) => write!(f, "LD ST, V{:X}", x),
+			Instruction::AddVx(x) => write!(f, "ADD I, V{:X}", x),
+			Instruction::LdVxDigitIntoF(x) => write!(f, "LD F, V{:X}", x),
+			Instruction::LdVxIntoBcd(x) => write!(f, "LD B, V{:X}", x),
+			Instruction::LdV0ToVxIntoI(x) => write!(f, "LD [I], V{:X}", x),
+			Instruction::LdIIntoV0ToVx(x) => write!(f, "LD V{:X}, [I]", x),
+			Instruction::Plane(mask) => write!(f, "PLANE {}", mask),
+			Instruction::LdILong => write!(f, "LD I, LONG"),
+			Instruction::Unknown(op) => write!(f, "Unknown opcode: 0x{:0>4X}", op)
+		}
+	}
+}
+
+/// Decode an opcode into an `Instruction`.
+pub fn decode(op: u16) -> Instruction
+{
+	let mut decoder = Decoder;
+	decode_opcode!(op, decoder)
+}
+
+/// Bitmask of the registers V0 through V$x.
+fn registers_up_to(x: u8) -> u16
+{
+	((1u32 << (x as u32 + 1)) - 1) as u16
+}
+
+/// Walk the ROM two bytes at a time and decode each opcode, yielding the address the
+/// opcode is loaded at together with the `Instruction`. A trailing odd byte is decoded
+/// as the high byte of an opcode. Data is decoded like code, and the address after
+/// `LdILong` is decoded as an instruction of its own.
+pub fn instructions<'a>(rom: &'a Rom) -> impl Iterator<Item = (u16, Instruction)> + 'a
+{
+	rom.bytes().chunks(2).enumerate().map(|(index, word)| {
+		let op = (word[0] as u16) << 8 | *word.get(1).unwrap_or(&0) as u16;
+		(0x200 + 2 * index as u16, decode(op))
+	})
+}
+
+/// Map each subroutine entry of $rom to the subroutines it calls in turn, sorted by
+/// address. Subroutines are found by following the code reachable from the program
+/// entry at 0x200, which is included as the root of the graph.
+///
+/// Only code at even offsets from 0x200 is followed. `JP V0, addr` ends a path as its
+/// target is not known statically, and so does leaving the ROM.
+pub fn call_graph(rom: &Rom) -> HashMap<u16, Vec<u16>>
+{
+	let code: HashMap<u16, Instruction> = instructions(rom).collect();
+	let mut graph = HashMap::new();
+	let mut entries = vec![0x200];
+
+	while let Some(entry) = entries.pop()
+	{
+		if graph.contains_key(&entry) || !code.contains_key(&entry) { continue; }
+
+		let mut calls = Vec::new();
+		let mut visited = HashSet::new();
+		let mut pending = vec![entry];
+		while let Some(addr) = pending.pop()
+		{
+			if !visited.insert(addr) { continue; }
+			let instruction = match code.get(&addr) { Some(instruction) => *instruction, None => continue };
+			if let Instruction::Call(target) = instruction {
+				calls.push(target);
+				entries.push(target);
+			}
+			pending.extend(next_addresses(addr, instruction));
+		}
+
+		calls.sort();
+		calls.dedup();
+		graph.insert(entry, calls);
+	}
+
+	graph
+}
+
+/// Every distinct opcode in the code reachable from the program entry at 0x200, following
+/// jumps and calls like `call_graph`. Handy for checking whether a ROM needs SuperCHIP or
+/// XO-CHIP opcodes before running it. The address following `LD I, LONG` is not an opcode
+/// and is left out.
+pub fn opcodes_used(rom: &Rom) -> BTreeSet<u16>
+{
+	let code: HashMap<u16, Instruction> = instructions(rom).collect();
+	let bytes = rom.bytes();
+	let mut opcodes = BTreeSet::new();
+	let mut visited = HashSet::new();
+	let mut pending = vec![0x200];
+
+	while let Some(addr) = pending.pop()
+	{
+		if !visited.insert(addr) { continue; }
+		let instruction = match code.get(&addr) { Some(instruction) => *instruction, None => continue };
+		let offset = (addr - 0x200) as usize;
+		opcodes.insert((bytes[offset] as u16) << 8 | *bytes.get(offset + 1).unwrap_or(&0) as u16);
+
+		if let Instruction::Call(target) = instruction { pending.push(target); }
+		pending.extend(next_addresses(addr, instruction));
+	}
+
+	opcodes
+}
+
+/// Addresses execution continues at after $instruction at $addr within the same
+/// subroutine. Calls are assumed to return, returns and `JP V0, addr` end the path.
+pub fn next_addresses(addr: u16, instruction: Instruction) -> Vec<u16>
+{
+	let next = addr.wrapping_add(instruction.length());
+	match instruction {
+		Instruction::Ret | Instruction::Exit | Instruction::JpV0(_) => vec![],
+		Instruction::Jp(target) => vec![target],
+		_ if instruction.is_branch() => vec![next, next.wrapping_add(2)],
+		_ => vec![next]
+	}
+}
+
+/// Target for `decode_opcode!` turning each opcode into its `Instruction`.
+struct Decoder;
+
+impl Decoder {
+	fn cls(&mut self) -> Instruction { Instruction::Cls }
+	fn ret(&mut self) -> Instruction { Instruction::Ret }
+	fn exit(&mut self) -> Instruction { Instruction::Exit }
+	fn sys(&mut self, addr: u16) -> Instruction { Instruction::Sys(addr) }
+	fn jp(&mut self, addr: u16) -> Instruction { Instruction::Jp(addr) }
+	fn call(&mut self, addr: u16) -> Instruction { Instruction::Call(addr) }
+	fn se(&mut self, reg: u8, val: u8) -> Instruction { Instruction::Se(reg, val) }
+	fn sne(&mut self, reg: u8, val: u8) -> Instruction { Instruction::Sne(reg, val) }
+	fn se_reg(&mut self, reg1: u8, reg2: u8) -> Instruction { Instruction::SeReg(reg1, reg2) }
+	fn ldx(&mut self, reg: u8, val: u8) -> Instruction { Instruction::Ldx(reg, val) }
+	fn add_byte(&mut self, reg: u8, byte: u8) -> Instruction { Instruction::AddByte(reg, byte) }
+	fn ld(&mut self, reg1: u8, reg2: u8) -> Instruction { Instruction::Ld(reg1, reg2) }
+	fn or(&mut self, reg1: u8, reg2: u8) -> Instruction { Instruction::Or(reg1, reg2) }
+	fn and(&mut self, reg1: u8, reg2: u8) -> Instruction { Instruction::And(reg1, reg2) }
+	fn xor(&mut self, reg1: u8, reg2: u8) -> Instruction { Instruction::Xor(reg1, reg2) }
+	fn add_reg(&mut self, reg1: u8, reg2: u8) -> Instruction { Instruction::AddReg(reg1, reg2) }
+	fn sub(&mut self, reg1: u8, reg2: u8) -> Instruction { Instruction::Sub(reg1, reg2) }
+	fn shr(&mut self, reg1: u8, reg2: u8) -> Instruction { Instruction::Shr(reg1, reg2) }
+	fn subn(&mut self, reg1: u8, reg2: u8) -> Instruction { Instruction::Subn(reg1, reg2) }
+	fn shl(&mut self, reg1: u8, reg2: u8) -> Instruction { Instruction::Shl(reg1, reg2) }
+	fn sne_reg(&mut self, reg1: u8, reg2: u8) -> Instruction { Instruction::SneReg(reg1, reg2) }
+	fn ldi(&mut self, val: u16) -> Instruction { Instruction::Ldi(val) }
+	fn jp_v0(&mut self, addr: u16) -> Instruction { Instruction::JpV0(addr) }
+	fn rnd(&mut self, reg: u8, byte: u8) -> Instruction { Instruction::Rnd(reg, byte) }
+	fn drw(&mut self, xreg: u8, yreg: u8, bytes: u8) -> Instruction { Instruction::Drw(xreg, yreg, bytes) }
+	fn skp(&mut self, reg: u8) -> Instruction { Instruction::Skp(reg) }
+	fn sknp(&mut self, reg: u8) -> Instruction { Instruction::Sknp(reg) }
+	fn ld_dt_into_vx(&mut self, reg: u8) -> Instruction { Instruction::LdDtIntoVx(reg) }
+	fn ld_k_into_vx(&mut self, reg: u8) -> Instruction { Instruction::LdKIntoVx(reg) }
+	fn ld_vx_into_dt(&mut self, reg: u8) -> Instruction { Instruction::LdVxIntoDt(reg) }
+	fn ld_vx_into_st(&mut self, reg: u8) -> Instruction { Instruction::LdVxIntoSt(reg) }
+	fn add_vx(&mut self, reg: u8) -> Instruction { Instruction::AddVx(reg) }
+	fn ld_vx_digit_into_f(&mut self, reg: u8) -> Instruction { Instruction::LdVxDigitIntoF(reg) }
+	fn ld_vx_into_bcd(&mut self, reg: u8) -> Instruction { Instruction::LdVxIntoBcd(reg) }
+	fn ld_v0_to_vx_into_i(&mut self, reg: u8) -> Instruction { Instruction::LdV0ToVxIntoI(reg) }
+	fn ld_i_into_v0_to_vx(&mut self, reg: u8) -> Instruction { Instruction::LdIIntoV0ToVx(reg) }
+	fn plane(&mut self, mask: u8) -> Instruction { Instruction::Plane(mask) }
+	fn ld_i_long(&mut self) -> Instruction { Instruction::LdILong }
+	fn unknown_opcode(&mut self, op: u16) -> Instruction { Instruction::Unknown(op) }
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[test]
+fn test_decode()
+{
+	assert!(decode(0x00E0) == Instruction::Cls);
+	assert!(decode(0x00FD) == Instruction::Exit);
+	assert!(decode(0xF000) == Instruction::LdILong);
+	assert!(decode(0xF000).length() == 4);
+	assert!(decode(0x1228) == Instruction::Jp(0x228));
+	assert!(decode(0x2300) == Instruction::Call(0x300));
+	assert!(decode(0x3A12) == Instruction::Se(0xA, 0x12));
+	assert!(decode(0x812E) == Instruction::Shl(0x1, 0x2));
+	assert!(decode(0xD125) == Instruction::Drw(0x1, 0x2, 0x5));
+	assert!(decode(0xE59E) == Instruction::Skp(0x5));
+	assert!(decode(0x812F) == Instruction::Unknown(0x812F));
+}
+
+#[test]
+fn test_encode()
+{
+	assert!(Instruction::Drw(0x1, 0x2, 0x5).encode() == 0xD125);
+	assert!(Instruction::LdV0ToVxIntoI(0xA).encode() == 0xFA55);
+	for op in 0..=0xFFFF { assert!(decode(op).encode() == op, "0x{:04X}", op); }
+}
+
+#[test]
+fn test_categories()
+{
+	let jp = decode(0x1228);
+	assert!(jp.is_jump() && !jp.is_branch());
+	assert!(jp.target() == Some(0x228));
+
+	let call = decode(0x2300);
+	assert!(call.is_jump() && !call.is_branch());
+	assert!(call.target() == Some(0x300));
+
+	let se = decode(0x3A12);
+	assert!(se.is_branch() && !se.is_jump());
+	assert!(se.target().is_none());
+
+	let skp = decode(0xE59E);
+	assert!(skp.is_branch() && !skp.is_jump());
+
+	for &op in [0x7A01, 0x8124, 0x8125, 0xF51E].iter()
+	{
+		let arithmetic = decode(op);
+		assert!(!arithmetic.is_jump() && !arithmetic.is_branch());
+		assert!(arithmetic.target().is_none());
+	}
+
+	assert!(decode(0xB300).is_jump());
+	assert!(decode(0xB300).target().is_none()); // Depends on V0
+	assert!(decode(0x00EE).is_jump());
+	assert!(decode(0x6000).length() == 2);
+}
+
+#[test]
+fn test_pattern()
+{
+	assert!(decode(0x00E0).pattern() == "00E0");
+	assert!(decode(0x8124).pattern() == "8xy4");
+	assert!(decode(0x8AB4).pattern() == "8xy4");
+	assert!(decode(0xF165).pattern() == "Fx65");
+	assert!(decode(0x812F).pattern() == "unknown");
+}
+
+#[test]
+fn test_mnemonic_styles()
+{
+	let cases = [
+		(0x00E0, "CLS", "clear"),
+		(0x600A, "LD V0, 0xA", "v0 := 0x0A"),
+		(0x3A12, "SE VA, 12", "if va != 0x12 then"),
+		(0x8124, "ADD V1, V2", "v1 += v2"),
+		(0x812E, "SHL V1", "v1 <<= v2"),
+		(0xA228, "LD I, 0x228", "i := 0x228"),
+		(0xD125, "DRW (V1, V2) for 5 bytes", "sprite v1 v2 5"),
+		(0xF329, "LD F, V3", "i := hex v3"),
+		(0xE59E, "SKP V5", "if v5 -key then"),
+		(0x5121, "Unknown opcode: 0x5121", "0x51 0x21")
+	];
+
+	for &(op, cowgod, octo) in cases.iter()
+	{
+		assert_eq!(decode(op).mnemonic(MnemonicStyle::Cowgod), cowgod);
+		assert_eq!(decode(op).mnemonic(MnemonicStyle::Octo), octo);
+	}
+	assert!(decode(0x600A).to_string() == "LD V0, 0xA");
+}
+
+#[test]
+fn test_instructions()
+{
+	// CLS ; LD V0, 0xA ; JP 0x200 ; odd byte
+	let rom = Rom::from_bytes(&[0x00, 0xE0, 0x60, 0x0A, 0x12, 0x00, 0xA3], "test".to_string());
+	let decoded: Vec<(u16, Instruction)> = instructions(&rom).collect();
+
+	assert!(decoded == vec![
+		(0x200, Instruction::Cls),
+		(0x202, Instruction::Ldx(0x0, 0x0A)),
+		(0x204, Instruction::Jp(0x200)),
+		(0x206, Instruction::Ldi(0x300))
+	]);
+	assert!(instructions(&Rom::from_bytes(&[], "empty".to_string())).next().is_none());
+}
+
+#[test]
+fn test_registers_read_and_written()
+{
+	assert!(decode(0x8124).registers_read() == 0b110); // ADD V1, V2
+	assert!(decode(0x8124).registers_written() == 0x8002); // V1 and VF
+	assert!(decode(0x6A05).registers_read() == 0);
+	assert!(decode(0x6A05).registers_written() == 1 << 0xA);
+	assert!(decode(0xF255).registers_read() == 0b111); // LD [I], V2
+	assert!(decode(0xFF65).registers_written() == 0xFFFF); // LD VF, [I]
+	assert!(decode(0xB300).registers_read() == 1); // JP V0, addr
+}
+
+#[test]
+fn test_call_graph()
+{
+	let rom = Rom::from_bytes(&[
+		0x22, 0x06, // 0x200: CALL 0x206
+		0x22, 0x0C, // 0x202: CALL 0x20C
+		0x12, 0x04, // 0x204: JP 0x204
+		0x22, 0x0C, // 0x206: CALL 0x20C
+		0x22, 0x06, // 0x208: CALL 0x206, recursion
+		0x00, 0xEE, // 0x20A: RET
+		0x30, 0x01, // 0x20C: SE V0, 0x01
+		0x22, 0x10, // 0x20E: CALL 0x210
+		0x00, 0xEE  // 0x210: RET
+	], "calls".to_string());
+	let graph = call_graph(&rom);
+
+	assert!(graph.len() == 4);
+	assert!(graph[&0x200] == vec![0x206, 0x20C]);
+	assert!(graph[&0x206] == vec![0x206, 0x20C]);
+	assert!(graph[&0x20C] == vec![0x210]);
+	assert!(graph[&0x210].is_empty());
+
+	assert!(call_graph(&Rom::from_bytes(&[], "empty".to_string())).is_empty());
+}
+
+#[test]
+fn test_opcodes_used()
+{
+	let rom = Rom::from_bytes(&[
+		0x60, 0x05, // 0x200: LD V0, 0x05
+		0x22, 0x08, // 0x202: CALL 0x208
+		0x12, 0x02, // 0x204: JP 0x202
+		0x00, 0xFF, // 0x206: data, never reached
+		0x00, 0xE0, // 0x208: CLS
+		0x60, 0x05, // 0x20A: LD V0, 0x05 again
+		0x00, 0xEE  // 0x20C: RET
+	], "opcodes".to_string());
+
+	let used: Vec<u16> = opcodes_used(&rom).into_iter().collect();
+	assert!(used == vec![0x00E0, 0x00EE, 0x1202, 0x2208, 0x6005]);
+
+	assert!(opcodes_used(&Rom::from_bytes(&[], "empty".to_string())).is_empty());
+}
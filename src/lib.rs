@@ -9,11 +9,31 @@ pub mod rom;
 pub mod ram;
 pub mod cpu;
 pub mod input;
+pub mod display;
+pub mod config;
+pub mod rewind;
+pub mod replay;
+pub mod instruction;
+pub mod test_support;
+pub mod emulator;
+pub mod audio;
+pub mod assembler;
+
+use std::error::Error;
+use std::fmt;
+use std::cell::Cell;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use rom::Rom;
 use ram::Ram;
-use cpu::Cpu;
-use input::Keyboard;
+use cpu::{Cpu, CpuError};
+use config::EmulatorConfig;
+#[cfg(test)]
+use config::Platform;
+use input::{ChannelInput, Input, Keyboard};
+use display::{Display, NullDisplay, TestDisplay};
 use disassembler::Disassembler;
 
 /// Disassemble the provided rom using the disassembler. Prints results to
@@ -24,13 +44,374 @@ pub fn disasm(rom: Rom)
 	dis.disasm(rom.length as u16);
 }
 
-/// Start emulation on the provided rom.
-pub fn emulate(rom: Rom)
+/// Errors that stop the emulation
+#[derive(Debug, PartialEq)]
+pub enum EmulatorError {
+	/// The emulated CPU raised an error
+	Cpu(CpuError),
+	/// The ROM contains no data to run
+	EmptyRom,
+	/// Writing the recorded audio failed
+	Audio(String)
+}
+
+impl From<CpuError> for EmulatorError
+{
+	fn from(err: CpuError) -> EmulatorError { EmulatorError::Cpu(err) }
+}
+
+impl fmt::Display for EmulatorError
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			EmulatorError::Cpu(ref err) => write!(f, "CPU error: {}", err),
+			EmulatorError::EmptyRom => write!(f, "ROM is empty"),
+			EmulatorError::Audio(ref err) => write!(f, "Audio recording error: {}", err)
+		}
+	}
+}
+
+impl Error for EmulatorError {}
+
+/// Start emulation on the provided rom. Runs until the program exits or the
+/// emulation fails.
+pub fn emulate(rom: Rom, config: EmulatorConfig) -> Result<(), EmulatorError>
+{
+	emulate_with(rom, config, |_| {})
+}
+
+/// Start emulation on the provided rom, calling $on_frame after every frame.
+/// Runs until the program exits or the emulation fails.
+///
+/// The callback runs on the emulation thread between frames, after the timers
+/// have been updated. It only gets shared access to the CPU, so it can inspect
+/// but not modify the emulation. The next frame starts once it returns.
+pub fn emulate_with<F>(rom: Rom, config: EmulatorConfig, mut on_frame: F) -> Result<(), EmulatorError>
+	where F: FnMut(&Cpu<Keyboard>)
 {
-	let mut ram = &mut Ram::new_from_rom(&rom);
+	check_rom(&rom)?;
+	let ram = &mut Ram::new_from_rom(&rom);
 	let keyboard = & Keyboard::new(); 
-	let mut cpu = Cpu::new(ram, keyboard);
-	loop {
-		cpu.step();
+	let display = &mut TestDisplay::new();
+	let mut cpu = Cpu::new(ram, keyboard, display);
+	cpu.configure(&config);
+
+	let frame_time = Duration::from_secs(1) / cpu.timer_hz();
+	while !cpu.is_halted() {
+		let frame_start = Instant::now();
+		run_frame(&mut cpu, &config, keyboard.turbo_held(), &mut on_frame)?;
+		for line in cpu.take_trace() { eprintln!("{}", line); }
+
+		let elapsed = frame_start.elapsed();
+		if elapsed < frame_time { thread::sleep(frame_time - elapsed); }
+	}
+	if config.blank_on_exit { display.shutdown(); }
+	Ok(())
+}
+
+/// Handle to an emulation running on its own thread, see `run_threaded`.
+pub struct EmulatorThread {
+	keys: Sender<[bool;16]>,
+	frames: Receiver<Vec<bool>>,
+	handle: JoinHandle<Result<(), EmulatorError>>
+}
+
+impl EmulatorThread
+{
+	/// Send new key states to the emulation. They take effect on the next frame.
+	/// Returns false if the emulation has stopped.
+	pub fn send_keys(&self, keys: [bool;16]) -> bool
+	{
+		self.keys.send(keys).is_ok()
+	}
+
+	/// Wait for the framebuffer of the next frame, in row-major order.
+	/// Returns None once the emulation has stopped.
+	pub fn recv_frame(&self) -> Option<Vec<bool>>
+	{
+		self.frames.recv().ok()
+	}
+
+	/// Stop the emulation and wait for the thread to finish. Returns the error that
+	/// stopped the emulation, if any.
+	pub fn stop(self) -> Result<(), EmulatorError>
+	{
+		let EmulatorThread { keys, frames, handle } = self;
+		drop(frames);
+		drop(keys);
+		handle.join().expect("emulation thread panicked")
+	}
+}
+
+/// Start emulation on the provided rom on a separate thread.
+///
+/// The CPU, memory and display live entirely on the emulation thread. Key states
+/// are sent in and the framebuffer of every frame is sent out over channels, see
+/// `EmulatorThread`. At most two frames are buffered; if the receiver falls behind
+/// the emulation waits for it instead of skipping frames.
+///
+/// The emulation runs until the program exits, the emulation fails or the
+/// `EmulatorThread` is stopped or dropped, which closes the frame channel.
+/// Trace lines, such as the warnings of `strict` mode, are printed to stderr after
+/// every frame as in `emulate`.
+pub fn run_threaded(rom: Rom, config: EmulatorConfig) -> EmulatorThread
+{
+	let (keys, key_receiver) = mpsc::channel();
+	let (frame_sender, frames) = mpsc::sync_channel(2);
+
+	let handle = thread::spawn(move || {
+		check_rom(&rom)?;
+		let ram = &mut Ram::new_from_rom(&rom);
+		let input = & ChannelInput::new(key_receiver);
+		let display = &mut TestDisplay::new();
+		let mut cpu = Cpu::new(ram, input, display);
+		cpu.configure(&config);
+
+		let frame_time = Duration::from_secs(1) / cpu.timer_hz();
+		let disconnected = Cell::new(false);
+		let mut send_frame = |cpu: &Cpu<ChannelInput>| send_framebuffer(&frame_sender, cpu.display(), &disconnected);
+
+		while !cpu.is_halted() && !disconnected.get() {
+			let frame_start = Instant::now();
+			run_frame(&mut cpu, &config, input.turbo_held(), &mut send_frame)?;
+			for line in cpu.take_trace() { eprintln!("{}", line); }
+
+			let elapsed = frame_start.elapsed();
+			if elapsed < frame_time { thread::sleep(frame_time - elapsed); }
+		}
+		if config.blank_on_exit && cpu.is_halted() {
+			display.shutdown();
+			send_framebuffer(&frame_sender, &*display, &disconnected);
+		}
+		Ok(())
+	});
+
+	EmulatorThread { keys, frames, handle }
+}
+
+/// Send the pixels of $display, flagging $disconnected if nobody is listening anymore.
+fn send_framebuffer(frames: &SyncSender<Vec<bool>>, display: &dyn Display, disconnected: &Cell<bool>)
+{
+	if frames.send(display.pixels()).is_err() { disconnected.set(true); }
+}
+
+/// Run the emulation on the provided rom for $cycles opcodes as fast as possible
+/// and return the elapsed time. Nothing is drawn and the timers are not updated,
+/// so only the interpreter core is measured.
+pub fn benchmark(rom: Rom, cycles: u64) -> Result<Duration, EmulatorError>
+{
+	check_rom(&rom)?;
+	let ram = &mut Ram::new_from_rom(&rom);
+	let keyboard = & Keyboard::new();
+	let display = &mut NullDisplay;
+	let mut cpu = Cpu::new(ram, keyboard, display);
+
+	let start = Instant::now();
+	for _ in 0..cycles {
+		cpu.step()?;
+	}
+	Ok(start.elapsed())
+}
+
+/// Refuse to run ROMs without any data, which would only execute zeroed memory.
+fn check_rom(rom: &Rom) -> Result<(), EmulatorError>
+{
+	if rom.length == 0 { Err(EmulatorError::EmptyRom) } else { Ok(()) }
+}
+
+/// Run a single frame: execute opcodes until `cycles_per_frame` cycles have been
+/// spent, `turbo_factor` times as many with $turbo set, update the timers and call
+/// $on_frame.
+fn run_frame<I, F>(cpu: &mut Cpu<I>, config: &EmulatorConfig, turbo: bool, on_frame: &mut F) -> Result<(), CpuError>
+	where I: Input, F: FnMut(&Cpu<I>)
+{
+	let end = cpu.cycles() + frame_cycles(config, turbo);
+	while cpu.cycles() < end {
+		if cpu.is_halted() { break; }
+		cpu.step()?;
+	}
+	cpu.tick_timers();
+	on_frame(cpu);
+	Ok(())
+}
+
+/// Number of cycles in a frame, scaled by `turbo_factor` with $turbo set.
+fn frame_cycles(config: &EmulatorConfig, turbo: bool) -> u64
+{
+	let factor = if turbo { config.turbo_factor.max(1) } else { 1 };
+	config.cycles_per_frame as u64 * factor as u64
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[test]
+fn test_emulator_error_from_cpu_error()
+{
+	let err: EmulatorError = CpuError::UnsupportedSys(0x123).into();
+	assert!(err == EmulatorError::Cpu(CpuError::UnsupportedSys(0x123)));
+	assert!(err.to_string() == "CPU error: Unsupported SYS call to 0x0123");
+}
+
+#[test]
+fn test_emulate_returns_cpu_error()
+{
+	let rom = Rom::new(&mut &[0x01, 0x23][..], "sys".to_string()).unwrap();
+	let mut config = EmulatorConfig::default();
+	config.quirks.sys_is_error = true;
+
+	assert!(emulate(rom, config) == Err(EmulatorError::Cpu(CpuError::UnsupportedSys(0x123))));
+}
+
+#[test]
+fn test_run_frame_calls_on_frame()
+{
+	let ram = &mut Ram::new();
+	let keyboard = & Keyboard::new();
+	let display = &mut TestDisplay::new();
+	let mut cpu = Cpu::new(ram, keyboard, display);
+	let config = EmulatorConfig::default();
+
+	let mut frames = 0;
+	for _ in 0..3 {
+		run_frame(&mut cpu, &config, false, &mut |_| frames += 1).unwrap();
+	}
+	assert!(frames == 3);
+}
+
+#[test]
+fn test_run_frame_turbo()
+{
+	let ram = &mut Ram::new();
+	let keyboard = & Keyboard::new();
+	let display = &mut TestDisplay::new();
+	let mut cpu = Cpu::new(ram, keyboard, display);
+	// LD V0, 0x4 ; LD ST, V0 ; JP 0x204
+	cpu.load_at(&[0x60, 0x04, 0xF0, 0x18, 0x12, 0x04], 0x200);
+	let config = EmulatorConfig { cycles_per_frame: 10, turbo_factor: 4, ..EmulatorConfig::default() };
+
+	assert!(frame_cycles(&config, false) == 10);
+	assert!(frame_cycles(&config, true) == 40);
+
+	run_frame(&mut cpu, &config, false, &mut |_| {}).unwrap();
+	assert!(cpu.cycles() == 10);
+	run_frame(&mut cpu, &config, true, &mut |_| {}).unwrap();
+	assert!(cpu.cycles() == 50);
+	run_frame(&mut cpu, &config, false, &mut |_| {}).unwrap();
+	assert!(cpu.cycles() == 60); // Back to normal speed once released
+
+	// Still one timer tick per frame: 4 frames of sound in total
+	assert!(cpu.is_beeping());
+	run_frame(&mut cpu, &config, false, &mut |_| {}).unwrap();
+	assert!(!cpu.is_beeping());
+}
+
+#[test]
+fn test_benchmark()
+{
+	// LD V0, 1 ; DRW V0, V0, 1 ; ADD V1, 1 ; JP 0x200
+	let rom = Rom::new(&mut &[0x60, 0x01, 0xD0, 0x01, 0x71, 0x01, 0x12, 0x00][..], "loop".to_string()).unwrap();
+	let elapsed = benchmark(rom, 10000).unwrap();
+	assert!(elapsed > Duration::new(0, 0));
+}
+
+#[test]
+fn test_benchmark_nested_calls()
+{
+	// 0x200: CALL 0x204 ; JP 0x200
+	// 0x204 + 4n: CALL 0x208 + 4n ; RET, 15 levels deep
+	// 0x240: RET
+	let mut data = vec![0x22, 0x04, 0x12, 0x00];
+	for n in 0..15u16 {
+		let next = 0x208 + 4 * n;
+		data.extend_from_slice(&[0x20 | (next >> 8) as u8, next as u8, 0x00, 0xEE]);
 	}
-}
\ No newline at end of file
+	data.extend_from_slice(&[0x00, 0xEE]);
+
+	// ADD V0, 1 ; JP 0x200, the same number of opcodes without touching the stack
+	let flat = [0x70, 0x01, 0x12, 0x00];
+
+	// With the stack pointer CALL and RET cost about as much as any other opcode,
+	// however deep the nesting. Scanning the stack for a free slot made them slower
+	// the deeper the calls went. The best of a few runs keeps scheduling noise out.
+	let cycles = 100000;
+	let best = |bytes: &[u8]| (0..3).map(|_| benchmark(Rom::from_bytes(bytes, "bench".to_string()), cycles).unwrap()).min().unwrap();
+	let nested = best(&data);
+	let baseline = best(&flat);
+	assert!(nested > Duration::new(0, 0));
+	assert!(nested < baseline * 4, "nested CALL/RET took {:?}, plain opcodes {:?}", nested, baseline);
+}
+
+#[test]
+fn test_emulate_stops_on_exit()
+{
+	// LD V0, 1 ; EXIT
+	let rom = Rom::new(&mut &[0x60, 0x01, 0x00, 0xFD][..], "exit".to_string()).unwrap();
+	let mut config = EmulatorConfig::default();
+	config.quirks.platform = Platform::SuperChip;
+
+	assert!(emulate(rom, config) == Ok(()));
+}
+
+#[test]
+fn test_run_threaded()
+{
+	// LD V0, F ; SKP V0 ; JP 0x202 ; LD F, V0 ; DRW V0, V0, 5 ; JP 0x20A
+	let program = [0x60, 0x0F, 0xE0, 0x9E, 0x12, 0x02, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x0A];
+	let rom = Rom::new(&mut &program[..], "threaded".to_string()).unwrap();
+	let emulator = run_threaded(rom, EmulatorConfig::default());
+
+	let frame = emulator.recv_frame().unwrap();
+	assert!(frame.len() == 64 * 32);
+	assert!(frame.iter().all(|p| !p)); // Waiting for the key
+
+	let mut keys = [false;16];
+	keys[0xF] = true;
+	assert!(emulator.send_keys(keys));
+
+	let mut drawn = false;
+	for _ in 0..10 {
+		let frame = emulator.recv_frame().unwrap();
+		if frame.iter().filter(|p| **p).count() == 11 { drawn = true; break; } // Digit F
+	}
+	assert!(drawn);
+	assert!(emulator.stop() == Ok(()));
+}
+
+#[test]
+fn test_run_threaded_blank_on_exit()
+{
+	// LD F, V0 ; DRW V0, V0, 5 ; EXIT
+	let program = [0xF0, 0x29, 0xD0, 0x05, 0x00, 0xFD];
+	let mut config = EmulatorConfig::default();
+	config.quirks.platform = Platform::SuperChip;
+
+	let frames: Vec<Vec<bool>> = run_threaded(Rom::from_bytes(&program, "exit".to_string()), config).frames.iter().collect();
+	assert!(frames.last().unwrap().iter().any(|&on| on)); // Digit 0 left on screen
+
+	config.blank_on_exit = true;
+	let frames: Vec<Vec<bool>> = run_threaded(Rom::from_bytes(&program, "exit".to_string()), config).frames.iter().collect();
+	assert!(frames.len() == 2);
+	assert!(frames[0].iter().any(|&on| on));
+	assert!(frames[1].iter().all(|&on| !on));
+}
+
+#[test]
+fn test_emulate_empty_rom()
+{
+	let rom = Rom::new(&mut &[][..], "empty".to_string()).unwrap();
+	assert!(rom.length == 0);
+	assert!(emulate(rom, EmulatorConfig::default()) == Err(EmulatorError::EmptyRom));
+	assert!(EmulatorError::EmptyRom.to_string() == "ROM is empty");
+}
+
+#[test]
+fn test_emulate_stops_at_instruction_budget()
+{
+	// JP 0x200
+	let rom = Rom::new(&mut &[0x12, 0x00][..], "loop".to_string()).unwrap();
+	let config = EmulatorConfig { max_instructions: Some(25), ..EmulatorConfig::default() };
+
+	assert!(emulate(rom, config) == Err(EmulatorError::Cpu(CpuError::InstructionBudgetExceeded(25))));
+}
@@ -28,44 +28,230 @@ static FONT_DATA: &'static [u8] = & [
 /// Memory trait provides the interface for memory implementations. Currently there is only the 
 /// `Ram` implementation.
 pub trait Memory {
-	/// Load a byte from RAM address $addr. Only the lowest 12 bits of the provided address byte
-	/// are used. 
-	fn lb(&mut self, addr: u16) -> u8;
+	/// Load a byte from RAM address $addr. Addresses past the end of memory wrap
+	/// around, see `size`.
+	fn lb(&self, addr: u16) -> u8;
 
-	/// Store a byte to RAM at address $addr. Only the lowest 12 bits of the provided address byte 
-	/// are used.
+	/// Store a byte to RAM at address $addr. Addresses past the end of memory wrap
+	/// around, see `size`.
 	fn sb(&mut self, addr: u16, value: u8);
+
+	/// Size of the memory in bytes.
+	fn size(&self) -> usize { 0x1000 }
+
+	/// Load a big-endian word from RAM addresses $addr and $addr+1. The byte at $addr
+	/// is the high byte of the word.
+	fn lw(&self, addr: u16) -> u16
+	{
+		let hi = (self.lb(addr) as u16) << 8;
+		let low = self.lb(addr.wrapping_add(1)) as u16;
+		low | hi
+	}
+
+	/// Copy $bytes into memory starting at address $addr. Bytes that do not fit below
+	/// the end of memory are left out. Returns the number of bytes copied.
+	fn load_at(&mut self, bytes: &[u8], addr: u16) -> usize
+	{
+		let addr = (addr as usize & (self.size() - 1)) as u16;
+		let length = bytes.len().min(self.size() - addr as usize);
+		for (offset, byte) in bytes[..length].iter().enumerate()
+		{
+			self.sb(addr + offset as u16, *byte);
+		}
+		length
+	}
 }
 
 /// Emulated RAM
 pub struct Ram {
-	/// RAM storage. CHIP-8 contains 4 kilobytes of RAM.
-	mem: [u8; 0x1000]
+	/// RAM storage. CHIP-8 contains 4 kilobytes of RAM, XO-CHIP 64 kilobytes.
+	mem: Vec<u8>
 }
 
 impl Ram {
 	/// Initialize a new RAM with the ROM provided copied into the work area at address 0x200 onwards.
 	pub fn new_from_rom(rom: &Rom) -> Ram 
 	{ 
-		let mut ram = Ram { mem: [0; 0x1000] };
+		Ram::new_from_rom_at(rom, 0x200)
+	}
+
+	/// Initialize a new RAM with the ROM provided copied into memory at address $base onwards.
+	/// ROM bytes that do not fit below the end of memory are left out.
+	pub fn new_from_rom_at(rom: &Rom, base: u16) -> Ram
+	{
+		let mut ram = Ram::new();
+		let base = base as usize & 0xFFF;
+		let length = rom.length.min(0x1000 - base);
 		ram.mem[0x000..0x050].clone_from_slice(&FONT_DATA[..]);
-		ram.mem[0x200..(0x200 + rom.length)].clone_from_slice(&rom.data[0..rom.length]);
+		ram.mem[base..(base + length)].clone_from_slice(&rom.data[0..length]);
+		ram
+	}
+
+	/// Initialize a new RAM with the ROM provided copied to 0x200 onwards, without the
+	/// font. For ROMs that use 0x000-0x04F for their own data; `LD F, Vx` then points
+	/// at whatever the program put there instead of the digit sprites.
+	pub fn new_from_rom_no_font(rom: &Rom) -> Ram
+	{
+		let mut ram = Ram::new();
+		let length = rom.length.min(0x1000 - 0x200);
+		ram.mem[0x200..(0x200 + length)].clone_from_slice(&rom.data[0..length]);
 		ram
 	}
 
 	/// Initialize new empty RAM
 	pub fn new() -> Ram 
 	{ 
-		Ram { mem: [0; 0x1000] }
+		Ram::with_size(0x1000)
+	}
+
+	/// Initialize new empty 64 kilobyte RAM for XO-CHIP. All 16 bits of addresses are used.
+	pub fn new_extended() -> Ram
+	{
+		Ram::with_size(0x10000)
+	}
+
+	/// Initialize new empty RAM of $size bytes, for machines with less memory than the
+	/// usual 4 kilobytes. Addresses wrap at $size, which has to be a power of two no
+	/// larger than 64 kilobytes.
+	pub fn with_size(size: usize) -> Ram
+	{
+		assert!(size.is_power_of_two() && size <= 0x10000, "RAM size must be a power of two up to 0x10000, got {:#X}", size);
+		Ram { mem: vec![0; size] }
+	}
+
+	/// Initialize RAM from a complete memory image. The image is used as is, including
+	/// the font and program areas.
+	pub fn from_image(image: [u8; 0x1000]) -> Ram
+	{
+		Ram { mem: image.to_vec() }
+	}
+
+	/// Returns the complete memory contents.
+	pub fn as_slice(&self) -> &[u8]
+	{
+		&self.mem
+	}
+
+	/// Returns the complete memory contents for modification.
+	pub fn as_mut_slice(&mut self) -> &mut [u8]
+	{
+		&mut self.mem
 	}
 }
 
 impl Memory for Ram {
-	/// Load a byte from RAM address $addr. Only the lowest 12 bits of the provided address byte
-	/// are used. 
-	fn lb(&mut self, addr: u16) -> u8 { self.mem[addr as usize & 0xFFF]}
-
-	/// Store a byte to RAM at address $addr. Only the lowest 12 bits of the provided address byte 
-	/// are used.
-	fn sb(&mut self, addr: u16, value: u8) { self.mem[addr as usize & 0xFFF] = value; }
-}
\ No newline at end of file
+	/// Load a byte from RAM address $addr. The address is masked to the size of the RAM:
+	/// 12 bits for the usual 4 kilobytes, all 16 for `new_extended`.
+	fn lb(&self, addr: u16) -> u8 { self.mem[addr as usize & (self.mem.len() - 1)] }
+
+	/// Store a byte to RAM at address $addr. The address is masked to the size of the RAM
+	/// like in `lb`.
+	fn sb(&mut self, addr: u16, value: u8) { let mask = self.mem.len() - 1; self.mem[addr as usize & mask] = value; }
+
+	fn size(&self) -> usize { self.mem.len() }
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[test]
+fn test_from_image()
+{
+	let rom = Rom::new(&mut &[0x12, 0x34, 0x56][..], "test".to_string()).unwrap();
+	let original = Ram::new_from_rom(&rom);
+
+	let mut image = [0u8; 0x1000];
+	image.copy_from_slice(original.as_slice());
+	let restored = Ram::from_image(image);
+
+	assert!(restored.as_slice() == original.as_slice());
+	assert!(restored.lb(0x000) == 0xF0); // Font
+	assert!(restored.lw(0x200) == 0x1234); // Program
+}
+
+#[test]
+fn test_as_mut_slice()
+{
+	let mut ram = Ram::new();
+	ram.as_mut_slice()[0x300] = 0xAB;
+	assert!(ram.lb(0x300) == 0xAB);
+}
+
+#[test]
+fn test_lw()
+{
+	let mut ram = Ram::new();
+	ram.sb(0x300, 0xAB);
+	ram.sb(0x301, 0xCD);
+
+	assert!(ram.lw(0x300) == 0xABCD);
+	assert!(ram.lw(0x301) == 0xCD00);
+}
+
+#[test]
+fn test_load_at()
+{
+	let mut ram = Ram::new();
+	assert!(ram.load_at(&[0x12, 0x34], 0x200) == 2);
+	assert!(ram.load_at(&[0xAB, 0xCD, 0xEF], 0x400) == 3);
+
+	assert!(ram.lw(0x200) == 0x1234);
+	assert!(ram.lw(0x400) == 0xABCD);
+	assert!(ram.lb(0x402) == 0xEF);
+
+	assert!(ram.load_at(&[0x01, 0x02, 0x03], 0xFFE) == 2); // Truncated at the end of memory
+	assert!(ram.lb(0xFFF) == 0x02);
+	assert!(ram.lb(0x000) == 0x00);
+}
+
+#[test]
+fn test_extended()
+{
+	let mut ram = Ram::new_extended();
+	assert!(ram.size() == 0x10000);
+
+	ram.sb(0xABCD, 0x12);
+	assert!(ram.lb(0xABCD) == 0x12);
+	assert!(ram.lb(0x0BCD) == 0x00); // No 12-bit masking
+	assert!(ram.load_at(&[0x01, 0x02], 0xFFFF) == 1);
+
+	let mut classic = Ram::new();
+	classic.sb(0xABCD, 0x34);
+	assert!(classic.lb(0x0BCD) == 0x34);
+}
+
+#[test]
+fn test_with_size()
+{
+	let mut ram = Ram::with_size(0x800);
+	assert!(ram.size() == 0x800);
+
+	ram.sb(0x7FF, 0x12);
+	ram.sb(0x800, 0x34); // Wraps to 0x000
+	assert!(ram.lb(0x000) == 0x34);
+	assert!(ram.lb(0xFFF) == 0x12);
+	assert!(ram.lw(0x7FF) == 0x1234);
+	assert!(ram.load_at(&[0x01, 0x02], 0x7FF) == 1);
+
+	assert!(Ram::new().size() == 0x1000);
+}
+
+#[test]
+#[should_panic]
+fn test_with_size_not_power_of_two()
+{
+	Ram::with_size(0xC00);
+}
+
+#[test]
+fn test_new_from_rom_no_font()
+{
+	let rom = Rom::from_bytes(&[0x12, 0x00], "loop".to_string());
+
+	let ram = Ram::new_from_rom_no_font(&rom);
+	assert!(ram.as_slice()[..0x200].iter().all(|&b| b == 0));
+	assert!(ram.as_slice()[0x200..0x202] == [0x12, 0x00]);
+
+	assert!(Ram::new_from_rom(&rom).as_slice()[..0x50] == FONT_DATA[..]);
+}
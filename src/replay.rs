@@ -0,0 +1,183 @@
+//! Recording and replaying of emulation sessions.
+//!
+//! A `Recorder` logs the key states of every frame together with the seed of the
+//! random number generator. Replaying the recording through `ScriptedInput` on a CPU
+//! created with `Cpu::new_seeded` reproduces the session exactly.
+//!
+//! Recordings are saved as text: a header line, the seed and one line per frame
+//! with the pressed keys as a hexadecimal bitmask (bit n set when key n is pressed).
+//!
+//! ```text
+//! CHIT8 REPLAY 1
+//! seed 0000000000C0FFEE
+//! 0000
+//! 0020
+//! ```
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use input::{keys_from_bitmask, keys_to_bitmask, ScriptedInput};
+
+/// Header identifying the recording format.
+const HEADER: &str = "CHIT8 REPLAY 1";
+
+/// Recording of the per-frame input of an emulation session.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recorder {
+	/// Seed of the random number generator of the recorded CPU
+	seed: u64,
+	/// Key states of each frame
+	frames: Vec<[bool;16]>
+}
+
+impl Recorder
+{
+	/// Start a new recording for a CPU seeded with $seed.
+	pub fn new(seed: u64) -> Recorder
+	{
+		Recorder { seed, frames: Vec::new() }
+	}
+
+	/// Record the key states of a single frame.
+	pub fn record_frame(&mut self, keys: [bool;16])
+	{
+		self.frames.push(keys);
+	}
+
+	/// Seed of the random number generator of the recorded session.
+	pub fn seed(&self) -> u64
+	{
+		self.seed
+	}
+
+	/// Key states of the recorded frames.
+	pub fn frames(&self) -> &[[bool;16]]
+	{
+		&self.frames
+	}
+
+	/// Create an input device replaying the recorded frames.
+	pub fn to_input(&self) -> ScriptedInput
+	{
+		ScriptedInput::new(self.frames.clone())
+	}
+
+	/// Write the recording to $w.
+	pub fn save<W: Write>(&self, w: &mut W) -> Result<(), io::Error>
+	{
+		writeln!(w, "{}", HEADER)?;
+		writeln!(w, "seed {:016X}", self.seed)?;
+		for keys in &self.frames
+		{
+			writeln!(w, "{:04X}", keys_to_bitmask(*keys))?;
+		}
+		Ok(())
+	}
+
+	/// Read a recording written by `save` from $r.
+	pub fn load<R: Read>(r: &mut R) -> Result<Recorder, io::Error>
+	{
+		let mut lines = BufReader::new(r).lines();
+
+		match lines.next() {
+			Some(Ok(ref line)) if line.trim() == HEADER => {},
+			Some(Err(err)) => return Err(err),
+			_ => return Err(invalid_data("missing replay header"))
+		}
+
+		let seed = match lines.next() {
+			Some(line) => {
+				let line = line?;
+				let value = line.trim().trim_start_matches("seed ");
+				u64::from_str_radix(value, 16).map_err(|_| invalid_data("invalid seed"))?
+			},
+			None => return Err(invalid_data("missing seed"))
+		};
+
+		let mut recorder = Recorder::new(seed);
+		for line in lines
+		{
+			let line = line?;
+			if line.trim().is_empty() { continue; }
+
+			let mask = u16::from_str_radix(line.trim(), 16).map_err(|_| invalid_data("invalid frame"))?;
+			recorder.record_frame(keys_from_bitmask(mask));
+		}
+
+		Ok(recorder)
+	}
+}
+
+fn invalid_data(msg: &str) -> io::Error
+{
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[cfg(test)]
+use cpu::{Cpu, CpuState};
+#[cfg(test)]
+use ram::{Memory, Ram};
+#[cfg(test)]
+use display::TestDisplay;
+
+/// Runs a ROM mixing random numbers and key input for the given frames and
+/// returns the final state.
+#[cfg(test)]
+fn run_session(seed: u64, input: &ScriptedInput, frames: usize) -> CpuState
+{
+	// RND V0, FF ; ADD V1, V0 ; LD V2, 5 ; SKP V2 ; ADD V3, 1 ; JP 0x200
+	let program = [0xC0, 0xFF, 0x81, 0x04, 0x62, 0x05, 0xE2, 0x9E, 0x73, 0x01, 0x12, 0x00];
+	let ram = &mut Ram::new();
+	for (addr, byte) in program.iter().enumerate() { ram.sb(0x200 + addr as u16, *byte); }
+	let disp = &mut TestDisplay::new();
+	let mut cpu = Cpu::new_seeded(ram, input, disp, seed);
+
+	for _ in 0..frames
+	{
+		for _ in 0..6 { cpu.step().unwrap(); }
+		input.next_frame();
+	}
+	cpu.snapshot()
+}
+
+#[test]
+fn test_replay_round_trip()
+{
+	let mut pressed = [false;16];
+	pressed[0x5] = true;
+
+	let mut recorder = Recorder::new(0x1234_5678_9ABC);
+	for frame in 0..8 { recorder.record_frame(if frame % 3 == 0 { pressed } else { [false;16] }); }
+	let original = run_session(recorder.seed(), &recorder.to_input(), 8);
+
+	let mut saved = Vec::new();
+	recorder.save(&mut saved).unwrap();
+	let loaded = Recorder::load(&mut &saved[..]).unwrap();
+	assert!(loaded == recorder);
+
+	let replayed = run_session(loaded.seed(), &loaded.to_input(), 8);
+	assert!(replayed == original);
+}
+
+#[test]
+fn test_replay_format()
+{
+	let mut pressed = [false;16];
+	pressed[0x5] = true;
+	pressed[0xF] = true;
+
+	let mut recorder = Recorder::new(0xC0FFEE);
+	recorder.record_frame([false;16]);
+	recorder.record_frame(pressed);
+
+	let mut saved = Vec::new();
+	recorder.save(&mut saved).unwrap();
+	assert!(String::from_utf8(saved).unwrap() == "CHIT8 REPLAY 1\nseed 0000000000C0FFEE\n0000\n8020\n");
+
+	assert!(Recorder::load(&mut &b"not a replay\n"[..]).is_err());
+	assert!(Recorder::load(&mut &b"CHIT8 REPLAY 1\nseed 0\nXYZ\n"[..]).is_err());
+}
@@ -0,0 +1,148 @@
+//! Rewind support for the CHIP-8 emulation.
+//!
+//! `Rewind` keeps a bounded history of recent `CpuState` snapshots, captured once per
+//! frame by the host loop, and allows stepping the emulation backwards through them.
+
+use std::collections::VecDeque;
+
+use cpu::{Cpu, CpuState};
+use input::Input;
+
+/// Ring buffer of the most recent emulation states.
+pub struct Rewind {
+	/// Captured states, oldest first
+	states: VecDeque<CpuState>,
+	/// Maximum number of states kept
+	capacity: usize,
+	/// Memory the states may occupy, if limited by `with_memory_limit`
+	memory_limit: Option<usize>
+}
+
+impl Rewind
+{
+	/// Create a rewind buffer keeping at most `capacity` states.
+	pub fn new(capacity: usize) -> Rewind
+	{
+		Rewind { states: VecDeque::with_capacity(capacity), capacity, memory_limit: None }
+	}
+
+	/// Create a rewind buffer keeping as many states as fit into `bytes` of memory.
+	/// The capacity assumes 4 kilobytes of RAM until the first capture, which sizes it
+	/// for the memory of the captured CPU.
+	pub fn with_memory_limit(bytes: usize) -> Rewind
+	{
+		Rewind { memory_limit: Some(bytes), ..Rewind::new(bytes / CpuState::size_bytes()) }
+	}
+
+	/// Capture the current state of the CPU. Discards the oldest state when the
+	/// buffer is full.
+	pub fn capture<I: Input>(&mut self, cpu: &Cpu<I>)
+	{
+		if let Some(bytes) = self.memory_limit
+		{
+			self.capacity = bytes / CpuState::size_bytes_for(cpu.memory_size());
+		}
+		if self.capacity == 0 { return; }
+		while self.states.len() >= self.capacity { self.states.pop_front(); }
+		self.states.push_back(cpu.snapshot());
+	}
+
+	/// Restore the CPU to the most recently captured state and drop it from the
+	/// buffer. Returns false if there was nothing to rewind to.
+	pub fn rewind<I: Input>(&mut self, cpu: &mut Cpu<I>) -> bool
+	{
+		match self.states.pop_back() {
+			Some(state) => { cpu.restore(&state); true },
+			None => false
+		}
+	}
+
+	/// Number of states currently available for rewinding.
+	pub fn len(&self) -> usize
+	{
+		self.states.len()
+	}
+
+	/// Returns true if there are no states to rewind to.
+	pub fn is_empty(&self) -> bool
+	{
+		self.states.is_empty()
+	}
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[cfg(test)]
+use ram::{Memory, Ram};
+#[cfg(test)]
+use input::Keyboard;
+#[cfg(test)]
+use display::TestDisplay;
+
+#[test]
+fn test_rewind()
+{
+	let ram = &mut Ram::new();
+	// ADD V0, 1 ; JP 0x200
+	for (addr, byte) in [0x70, 0x01, 0x12, 0x00].iter().enumerate() { ram.sb(0x200 + addr as u16, *byte); }
+	let kb = & Keyboard::new();
+	let disp = &mut TestDisplay::new();
+	let mut cpu = Cpu::new(ram, kb, disp);
+	let mut rewind = Rewind::new(4);
+
+	let mut states = Vec::new();
+	for _ in 0..3
+	{
+		rewind.capture(&cpu);
+		states.push(cpu.snapshot());
+		cpu.step().unwrap();
+	}
+
+	assert!(rewind.len() == 3);
+	assert!(rewind.rewind(&mut cpu));
+	assert!(cpu.snapshot() == states[2]);
+	assert!(rewind.rewind(&mut cpu));
+	assert!(cpu.snapshot() == states[1]);
+	assert!(rewind.rewind(&mut cpu));
+	assert!(cpu.snapshot() == states[0]);
+	assert!(!rewind.rewind(&mut cpu));
+}
+
+#[test]
+fn test_rewind_is_bounded()
+{
+	let ram = &mut Ram::new();
+	let kb = & Keyboard::new();
+	let disp = &mut TestDisplay::new();
+	let cpu = Cpu::new(ram, kb, disp);
+	let mut rewind = Rewind::new(2);
+
+	for _ in 0..5 { rewind.capture(&cpu); }
+	assert!(rewind.len() == 2);
+
+	assert!(Rewind::with_memory_limit(0).is_empty());
+	assert!(Rewind::with_memory_limit(10 * CpuState::size_bytes()).capacity == 10);
+}
+
+#[test]
+fn test_rewind_extended_memory()
+{
+	let ram = &mut Ram::new_extended();
+	let kb = & Keyboard::new();
+	let disp = &mut TestDisplay::new();
+	let mut cpu = Cpu::new(ram, kb, disp);
+	let mut rewind = Rewind::with_memory_limit(10 * CpuState::size_bytes());
+
+	cpu.poke(0xF000, 0xAB);
+	rewind.capture(&cpu);
+	assert!(cpu.snapshot().memory_size() == 0x10000);
+	assert!(rewind.capacity == 10 * CpuState::size_bytes() / CpuState::size_bytes_for(0x10000));
+
+	cpu.poke(0xF000, 0xCD);
+	cpu.poke(0xFFFF, 0x01);
+	assert!(rewind.rewind(&mut cpu));
+	assert!(cpu.peek(0xF000) == 0xAB);
+	assert!(cpu.peek(0xFFFF) == 0x00);
+}
@@ -1,8 +1,47 @@
 //! ROM loaded into the CHIP-8 emulator / disassembler.
 
-use std::io::{self, Read};
+use std::error::Error;
+use std::io::{self, Read, Write};
 use std::fmt;
 
+use config::Platform;
+use instruction::{self, Instruction};
+
+/// Errors loading a ROM
+#[derive(Debug)]
+pub enum RomError {
+	/// Reading the ROM failed
+	Io(io::Error),
+	/// The ROM has more bytes than fit into memory from 0x200 on
+	TooLarge { size: usize },
+	/// The ROM contains no data
+	Empty,
+	/// A token of a hex dump is not a byte
+	BadHex { token: String },
+	/// The file is shorter than the number of leading bytes to skip
+	SkipPastEnd { skip_bytes: usize, size: usize }
+}
+
+impl From<io::Error> for RomError
+{
+	fn from(err: io::Error) -> RomError { RomError::Io(err) }
+}
+
+impl fmt::Display for RomError
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			RomError::Io(ref err) => write!(f, "I/O error: {}", err),
+			RomError::TooLarge { size } => write!(f, "ROM is {} bytes, at most 3232 bytes fit into memory", size),
+			RomError::Empty => write!(f, "ROM is empty"),
+			RomError::BadHex { ref token } => write!(f, "not a byte: {}", token),
+			RomError::SkipPastEnd { skip_bytes, size } => write!(f, "cannot skip {} bytes of a {} byte file", skip_bytes, size)
+		}
+	}
+}
+
+impl Error for RomError {}
+
 /// Struct describing the ROM file
 pub struct Rom {
 	/// File name of the ROM. Used for identification.
@@ -17,15 +56,170 @@ pub struct Rom {
 impl Rom {
 	/// Create a new ROM from the provided file and file name.
 	/// File name is only used for identification and can be omitted.
-	/// Only the first 3232 bytes of the ROM are loaded if a larger file is provided
-	/// as this is the maximum number of bytes that can be copied into the CHIP-8 memory.
-	pub fn new(readable: &mut Read, filename: String) -> Result<Rom, io::Error>
+	/// Fails with `RomError::TooLarge` if the file is larger than 3232 bytes, as this is
+	/// the maximum number of bytes that can be copied into the CHIP-8 memory.
+	pub fn new(readable: &mut Read, filename: String) -> Result<Rom, RomError>
 	{
-		let mut buffer = [0u8; 0xCA0]; // Maximum size for ROMs is 3232 bytes
-		let length = match readable.read(&mut buffer) { Ok(l) => l, Err(err) => return Err(err) };
+		let mut bytes = Vec::new();
+		readable.read_to_end(&mut bytes)?;
+		if bytes.len() > 0xCA0 { return Err(RomError::TooLarge { size: bytes.len() }); } // Maximum size for ROMs is 3232 bytes
 
-		Ok(Rom { data: buffer, filename: filename, length: length })
+		Ok(Rom::from_bytes(&bytes, filename))
 	}
+
+	/// Create a new ROM like `new`, skipping the first $skip_bytes bytes of the file.
+	/// Some ROM dumps carry leading padding or a small header in front of the program.
+	/// Fails if the file is shorter than $skip_bytes.
+	pub fn new_skipping(readable: &mut dyn Read, filename: String, skip_bytes: usize) -> Result<Rom, RomError>
+	{
+		let skipped = io::copy(&mut readable.take(skip_bytes as u64), &mut io::sink())?;
+		if skipped < skip_bytes as u64
+		{
+			return Err(RomError::SkipPastEnd { skip_bytes, size: skipped as usize });
+		}
+
+		Rom::new(readable, filename)
+	}
+
+	/// Create a new ROM from bytes already in memory, for example a program put
+	/// together by hand or by tooling. Only the first 3232 bytes are used.
+	pub fn from_bytes(bytes: &[u8], filename: String) -> Rom
+	{
+		let mut data = [0u8; 0xCA0];
+		let length = bytes.len().min(data.len());
+		data[..length].clone_from_slice(&bytes[..length]);
+
+		Rom { data, filename, length }
+	}
+
+	/// Create a new ROM from a hex dump such as `00 E0, 0x60 0x0A`, the way ROMs are often
+	/// shared on forums. Bytes are separated by whitespace or commas and may have a `0x`
+	/// prefix. Fails if any token is not a byte or if there are more bytes than fit into memory.
+	pub fn from_hex_str(s: &str, filename: String) -> Result<Rom, RomError>
+	{
+		let mut bytes = Vec::new();
+		for token in s.split(|c: char| c.is_whitespace() || c == ',').filter(|t| !t.is_empty())
+		{
+			let digits = token.trim_start_matches("0x").trim_start_matches("0X");
+			if digits.is_empty() || digits.len() > 2
+			{
+				return Err(RomError::BadHex { token: token.to_string() });
+			}
+			let byte = u8::from_str_radix(digits, 16)
+				.map_err(|_| RomError::BadHex { token: token.to_string() })?;
+			bytes.push(byte);
+		}
+		if bytes.len() > 0xCA0 { return Err(RomError::TooLarge { size: bytes.len() }); }
+
+		Ok(Rom::from_bytes(&bytes, filename))
+	}
+
+	/// Returns the ROM, or `RomError::Empty` if it contains no data. For loaders that
+	/// want to reject empty files up front rather than when the emulation starts.
+	pub fn non_empty(self) -> Result<Rom, RomError>
+	{
+		if self.length == 0 { Err(RomError::Empty) } else { Ok(self) }
+	}
+
+	/// Returns a copy of the ROM with the bytes of every opcode swapped, for ROMs
+	/// stored in little-endian byte order. A trailing odd byte is left as is.
+	pub fn byteswapped(&self) -> Rom
+	{
+		let mut bytes = self.bytes().to_vec();
+		for pair in bytes.chunks_mut(2).filter(|pair| pair.len() == 2) { pair.swap(0, 1); }
+		Rom::from_bytes(&bytes, self.filename.clone())
+	}
+
+	/// Returns the loaded ROM bytes.
+	pub fn bytes(&self) -> &[u8]
+	{
+		&self.data[..self.length]
+	}
+}
+
+/// Write $bytes to $out as a binary ROM file. CHIP-8 programs are stored as a plain
+/// byte stream with big-endian opcodes, so the bytes are written as is regardless
+/// of the host byte order.
+pub fn write_rom<W: Write>(bytes: &[u8], out: &mut W) -> io::Result<()>
+{
+	out.write_all(bytes)?;
+	out.flush()
+}
+
+/// Built-in program drawing "CHIT8" in the middle of the screen, then idling.
+static LOGO: &[u8] = &[
+	0x00, 0xE0, // 0x200: CLS
+	0xA2, 0x1A, // 0x202: LD I, 0x21A
+	0x60, 0x14, // 0x204: LD V0, 20
+	0x61, 0x0D, // 0x206: LD V1, 13
+	0x62, 0x05, // 0x208: LD V2, 5
+	0x63, 0x05, // 0x20A: LD V3, 5
+	0xD0, 0x15, // 0x20C: DRW V0, V1, 5
+	0xF2, 0x1E, // 0x20E: ADD I, V2
+	0x70, 0x05, // 0x210: ADD V0, 5
+	0x73, 0xFF, // 0x212: ADD V3, 0xFF
+	0x33, 0x00, // 0x214: SE V3, 0
+	0x12, 0x0C, // 0x216: JP 0x20C
+	0x12, 0x18, // 0x218: JP 0x218
+	0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+	0x90, 0x90, 0xF0, 0x90, 0x90, // H
+	0xE0, 0x40, 0x40, 0x40, 0xE0, // I
+	0xE0, 0x40, 0x40, 0x40, 0x40, // T
+	0xF0, 0x90, 0xF0, 0x90, 0xF0  // 8
+];
+
+/// Returns a tiny built-in ROM that clears the screen and draws the "CHIT8" logo.
+/// Useful as a first run that needs no ROM file, and as an end-to-end smoke test.
+pub fn logo_rom() -> Rom
+{
+	Rom::from_bytes(LOGO, "logo".to_string())
+}
+
+/// Guess whether $rom was stored with the bytes of each opcode swapped.
+///
+/// Counts the words that do not decode, or decode to `SYS`, which programs hardly
+/// ever use. Swapped code is full of both, as the low byte of many opcodes is small.
+/// The ROM is considered swapped when at least a quarter of its words are suspicious
+/// and swapping the bytes makes fewer words suspicious. ROMs that are mostly data
+/// may still be misjudged.
+pub fn looks_byteswapped(rom: &Rom) -> bool
+{
+	let suspicious = |bytes: &[u8]| bytes.chunks(2).filter(|word| word.len() == 2)
+		.filter(|word| matches!(instruction::decode((word[0] as u16) << 8 | word[1] as u16), Instruction::Unknown(_) | Instruction::Sys(_)))
+		.count();
+
+	let words = rom.length / 2;
+	let normal = suspicious(rom.bytes());
+	let swapped = suspicious(rom.byteswapped().bytes());
+	words > 0 && normal * 4 >= words && swapped < normal
+}
+
+/// Guess which platform $rom was written for from the opcodes it contains.
+///
+/// This is best-effort: ROMs mix code and data, so sprite data can look like opcodes
+/// of another platform. Only opcodes that are unlikely to appear by accident are
+/// considered, and a ROM without any of them is assumed to be plain CHIP-8.
+pub fn guess_platform(rom: &Rom) -> Platform
+{
+	let mut platform = Platform::Chip8;
+
+	for word in rom.bytes().chunks(2).filter(|word| word.len() == 2)
+	{
+		let op = (word[0] as u16) << 8 | word[1] as u16;
+		match op {
+			// Save and load register ranges, plane selection, audio pattern, pitch, scroll up
+			op if op & 0xF00F == 0x5002 || op & 0xF00F == 0x5003 => return Platform::XoChip,
+			op if op & 0xF0FF == 0xF001 || op == 0xF002 || op & 0xF0FF == 0xF03A => return Platform::XoChip,
+			op if op & 0xFFF0 == 0x00D0 && op != 0x00D0 => return Platform::XoChip,
+			// Scroll down, scroll left/right, exit, low/high resolution, big font, flags
+			op if op & 0xFFF0 == 0x00C0 && op != 0x00C0 => platform = Platform::SuperChip,
+			0x00FB..=0x00FF => platform = Platform::SuperChip,
+			op if op & 0xF0FF == 0xF030 || op & 0xF0FF == 0xF075 || op & 0xF0FF == 0xF085 => platform = Platform::SuperChip,
+			_ => {}
+		}
+	}
+
+	platform
 }
 
 impl fmt::Display for Rom
@@ -37,4 +231,106 @@ impl fmt::Display for Rom
             self.length
         )
     }
-}
\ No newline at end of file
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[test]
+fn test_write_rom_round_trip()
+{
+	// LD V0, 5 ; LD F, V0 ; DRW V0, V0, 5
+	let program = [0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05];
+	let mut file = Vec::new();
+	write_rom(&program, &mut file).unwrap();
+	assert!(file == program);
+
+	let rom = Rom::new(&mut &file[..], "written".to_string()).unwrap();
+	assert!(rom.bytes() == &program[..]);
+	assert!(Rom::from_bytes(&program, "direct".to_string()).bytes() == rom.bytes());
+
+	assert!(Rom::from_bytes(&[0u8; 0x1000], "large".to_string()).length == 0xCA0);
+}
+
+#[test]
+fn test_guess_platform()
+{
+	// LD V0, 5 ; LD F, V0 ; DRW V0, V0, 5
+	let chip8 = Rom::from_bytes(&[0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05], "chip8".to_string());
+	assert!(guess_platform(&chip8) == Platform::Chip8);
+
+	// HIGH ; LD HF, V0 ; EXIT
+	let superchip = Rom::from_bytes(&[0x00, 0xFF, 0xF0, 0x30, 0x00, 0xFD], "superchip".to_string());
+	assert!(guess_platform(&superchip) == Platform::SuperChip);
+
+	// SCD 4
+	assert!(guess_platform(&Rom::from_bytes(&[0x00, 0xC4], "scroll".to_string())) == Platform::SuperChip);
+
+	// HIGH ; PLANE 3
+	let xochip = Rom::from_bytes(&[0x00, 0xFF, 0xF3, 0x01], "xochip".to_string());
+	assert!(guess_platform(&xochip) == Platform::XoChip);
+
+	assert!(guess_platform(&Rom::from_bytes(&[], "empty".to_string())) == Platform::Chip8);
+}
+
+#[test]
+fn test_looks_byteswapped()
+{
+	let logo = logo_rom();
+	let digits = Rom::from_bytes(include_bytes!("../tests/roms/digits.ch8"), "digits.ch8".to_string());
+
+	assert!(!looks_byteswapped(&logo));
+	assert!(!looks_byteswapped(&digits));
+	assert!(looks_byteswapped(&logo.byteswapped()));
+	assert!(looks_byteswapped(&digits.byteswapped()));
+	assert!(!looks_byteswapped(&Rom::from_bytes(&[], "empty".to_string())));
+
+	let odd = Rom::from_bytes(&[0x12, 0x00, 0xFF], "odd".to_string());
+	assert!(odd.byteswapped().bytes() == [0x00, 0x12, 0xFF]);
+	assert!(odd.byteswapped().byteswapped().bytes() == odd.bytes());
+}
+
+#[test]
+fn test_from_hex_str()
+{
+	let rom = Rom::from_hex_str("00 E0, 0x60 0x0a\n\t12,00", "hex".to_string()).unwrap();
+	assert!(rom.bytes() == &[0x00, 0xE0, 0x60, 0x0A, 0x12, 0x00][..]);
+
+	assert!(Rom::from_hex_str("", "empty".to_string()).unwrap().length == 0);
+	assert!(Rom::from_hex_str("00 E0 G1", "bad".to_string()).is_err());
+	assert!(Rom::from_hex_str("00E0", "word".to_string()).is_err());
+	assert!(Rom::from_hex_str("0x", "prefix".to_string()).is_err());
+}
+
+#[test]
+fn test_new_skipping()
+{
+	// Two byte header followed by CLS ; JP 0x200
+	let file = [0xAA, 0x55, 0x00, 0xE0, 0x12, 0x00];
+	let rom = Rom::new_skipping(&mut &file[..], "header".to_string(), 2).unwrap();
+	assert!(rom.bytes() == &[0x00, 0xE0, 0x12, 0x00][..]);
+
+	assert!(Rom::new_skipping(&mut &file[..], "none".to_string(), 0).unwrap().bytes() == &file[..]);
+	assert!(Rom::new_skipping(&mut &file[..], "all".to_string(), 6).unwrap().length == 0);
+	assert!(Rom::new_skipping(&mut &file[..], "short".to_string(), 7).is_err());
+}
+
+#[test]
+fn test_rom_error()
+{
+	let io = RomError::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+	assert!(io.to_string() == "I/O error: no such file");
+	assert!(RomError::TooLarge { size: 4000 }.to_string() == "ROM is 4000 bytes, at most 3232 bytes fit into memory");
+	assert!(RomError::Empty.to_string() == "ROM is empty");
+	assert!(RomError::BadHex { token: "G1".to_string() }.to_string() == "not a byte: G1");
+	assert!(RomError::SkipPastEnd { skip_bytes: 7, size: 6 }.to_string() == "cannot skip 7 bytes of a 6 byte file");
+
+	match Rom::from_hex_str("00 E0 G1", "bad".to_string()) { Err(RomError::BadHex { token }) => assert!(token == "G1"), _ => panic!() }
+	match Rom::from_hex_str(&"00 ".repeat(0xCA1), "large".to_string()) { Err(RomError::TooLarge { size }) => assert!(size == 0xCA1), _ => panic!() }
+	match Rom::new_skipping(&mut &[0x00][..], "short".to_string(), 2) { Err(RomError::SkipPastEnd { .. }) => {}, _ => panic!() }
+	match Rom::new(&mut &[0u8; 0x1000][..], "large".to_string()) { Err(RomError::TooLarge { size }) => assert!(size == 0x1000), _ => panic!() }
+	assert!(Rom::new(&mut &[0u8; 0xCA0][..], "largest".to_string()).unwrap().length == 0xCA0);
+	match Rom::from_bytes(&[], "empty".to_string()).non_empty() { Err(RomError::Empty) => {}, _ => panic!() }
+	assert!(Rom::from_bytes(&[0x00, 0xE0], "cls".to_string()).non_empty().is_ok());
+}
@@ -0,0 +1,123 @@
+//! Helpers for writing tests against the display and the disassembler.
+//!
+//! Expected screens can be written as multi-line strings where `#` is a lit pixel
+//! and `.` an unlit one, and compared against `TestDisplay::framebuffer`:
+//!
+//! ```text
+//! ####
+//! #...
+//! ####
+//! ```
+
+use display::{WIDTH, HEIGHT};
+use disassembler::{Disassembler, DisasmOptions};
+use assembler::{self, AsmError};
+use ram::Ram;
+use rom::Rom;
+
+/// Disassemble $rom and assemble the listing again, returning the assembled bytes.
+/// Words that do not decode are written as `DB` data. For a faithful disassembler
+/// and assembler the result equals the ROM, except in these known cases:
+///
+/// - `SHR`/`SHL` are written without Vy, so a non-zero Vy nibble assembles to 0.
+/// - A trailing odd byte is disassembled together with the byte after the ROM; the
+///   result is cut to the length of the ROM to leave it out.
+pub fn disasm_round_trip(rom: &Rom) -> Result<Vec<u8>, AsmError>
+{
+	let mut ram = Ram::new_from_rom(rom);
+	let mut dis = Disassembler { pc: 0x200, ram: &mut ram };
+	let options = DisasmOptions { skip_unknown_as_data: true, ..DisasmOptions::default() };
+	let listing = dis.disasm_lines(rom.length as u16, options).join("\n");
+
+	let mut bytes = assembler::assemble(&listing)?;
+	bytes.truncate(rom.length);
+	Ok(bytes)
+}
+
+/// Returns true if both framebuffers have the same size and the same pixels lit.
+pub fn framebuffer_eq(a: &[bool], b: &[bool]) -> bool
+{
+	a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a == b)
+}
+
+/// Build a standard resolution framebuffer from rows of `#` (lit) and `.` (unlit).
+/// Leading and trailing blank lines and indentation are ignored. Rows shorter than
+/// the display and missing rows at the bottom are filled with unlit pixels, so only
+/// the top left corner of the screen needs to be written out.
+pub fn framebuffer_from_ascii(s: &str) -> Vec<bool>
+{
+	let mut pixels = vec![false; (WIDTH * HEIGHT) as usize];
+	let rows = s.trim().lines().map(|line| line.trim());
+
+	for (y, row) in rows.take(HEIGHT as usize).enumerate()
+	{
+		for (x, c) in row.chars().take(WIDTH as usize).enumerate()
+		{
+			pixels[y * WIDTH as usize + x] = c == '#';
+		}
+	}
+	pixels
+}
+
+/// Render the difference between the standard resolution framebuffers $a and $b as
+/// text, one line per row: `#` for pixels lit in both, `.` for pixels unlit in both,
+/// `+` for pixels only lit in $b and `-` for pixels only lit in $a. Pixels missing
+/// from a shorter framebuffer count as unlit. Makes failing screen comparisons readable.
+pub fn framebuffer_diff_ascii(a: &[bool], b: &[bool]) -> String
+{
+	let mut text = String::with_capacity(((WIDTH + 1) * HEIGHT) as usize);
+	for y in 0..HEIGHT as usize {
+		for x in 0..WIDTH as usize {
+			let index = y * WIDTH as usize + x;
+			let before = a.get(index).cloned().unwrap_or(false);
+			let after = b.get(index).cloned().unwrap_or(false);
+			text.push(match (before, after) {
+				(true, true) => '#',
+				(false, false) => '.',
+				(false, true) => '+',
+				(true, false) => '-'
+			});
+		}
+		text.push('\n');
+	}
+	text
+}
+
+// ---------
+// - TESTS -
+//----------
+
+#[test]
+fn test_framebuffer_from_ascii()
+{
+	let pixels = framebuffer_from_ascii("
+		#.#
+		.#.
+	");
+
+	assert!(pixels.len() == (WIDTH * HEIGHT) as usize);
+	assert!(pixels[0] && !pixels[1] && pixels[2]);
+	assert!(!pixels[WIDTH as usize] && pixels[WIDTH as usize + 1]);
+	assert!(pixels.iter().filter(|p| **p).count() == 3);
+
+	assert!(framebuffer_eq(&pixels, &framebuffer_from_ascii("#.#\n.#.")));
+	assert!(!framebuffer_eq(&pixels, &framebuffer_from_ascii("#.#")));
+	assert!(!framebuffer_eq(&pixels, &pixels[1..]));
+}
+
+#[test]
+fn test_framebuffer_diff_ascii()
+{
+	let a = framebuffer_from_ascii("#.#\n.#.");
+	let b = framebuffer_from_ascii("#.#\n..#");
+
+	let diff = framebuffer_diff_ascii(&a, &b);
+	let rows: Vec<&str> = diff.lines().collect();
+	assert!(rows.len() == HEIGHT as usize);
+	assert!(rows.iter().all(|row| row.len() == WIDTH as usize));
+	assert!(rows[0].starts_with("#.#..."));
+	assert!(rows[1].starts_with(".-+..."));
+	assert!(rows[2..].iter().all(|row| row.chars().all(|c| c == '.')));
+
+	assert!(!framebuffer_diff_ascii(&a, &a).contains(['+', '-']));
+}
@@ -0,0 +1,70 @@
+//! End-to-end tests running complete ROMs through the emulator.
+//!
+//! Each test loads a ROM bundled under `tests/roms/`, runs it for a fixed number of
+//! cycles against `TestDisplay` and `ScriptedInput` and checks the resulting screen.
+//!
+//! To add a ROM, place the binary in `tests/roms/`, find out how many cycles it needs
+//! to finish drawing and add a test calling `run_rom` with the expected lit pixel
+//! count and a few pixels that must be lit.
+
+extern crate chip8;
+
+use chip8::cpu::Cpu;
+use chip8::display::{Display, TestDisplay};
+use chip8::input::ScriptedInput;
+use chip8::ram::Ram;
+use chip8::rom::{self, Rom};
+use chip8::test_support::disasm_round_trip;
+
+/// Cycles run per entry of the input script.
+const CYCLES_PER_FRAME: usize = 10;
+
+/// Runs the ROM for the given number of cycles and returns the resulting display.
+/// The input script moves on to its next entry every `CYCLES_PER_FRAME` cycles.
+fn run_rom(bytes: &[u8], cycles: usize, input: &ScriptedInput) -> TestDisplay
+{
+	let rom = Rom::new(&mut &bytes[..], "test".to_string()).unwrap();
+	let ram = &mut Ram::new_from_rom(&rom);
+	let mut display = TestDisplay::new();
+	{
+		let mut cpu = Cpu::new(ram, input, &mut display);
+		for cycle in 1..=cycles
+		{
+			cpu.step().unwrap();
+			if cycle % CYCLES_PER_FRAME == 0 { input.next_frame(); }
+		}
+	}
+	display
+}
+
+/// `digits.ch8` draws the digits 0 to 7 on the top row, waits for a key press
+/// and draws the pressed key's digit on the bottom row.
+#[test]
+fn test_digits_rom()
+{
+	// Key A goes down once the ROM waits for it, a key held from the start would not count
+	let mut keys = [false; 16];
+	keys[0xA] = true;
+	let mut script = vec![[false; 16]; 15];
+	script.push(keys);
+	let input = ScriptedInput::new(script);
+
+	let display = run_rom(include_bytes!("roms/digits.ch8"), 200, &input);
+
+	assert_eq!(display.count_lit_pixels(), 111);
+	assert!(display.get_pixel(0, 2)); // Top left corner of 0
+	assert!(display.get_pixel(36, 6)); // Bottom right corner of 7's stem
+	assert!(display.get_pixel(0, 24)); // Bottom left leg of A
+	assert!(!display.get_pixel(1, 23)); // Gap inside A
+}
+
+/// Disassembling the bundled ROMs and assembling the listing gives back the ROM.
+#[test]
+fn test_disasm_round_trip()
+{
+	let logo = rom::logo_rom();
+	assert!(disasm_round_trip(&logo).unwrap() == logo.bytes());
+
+	let digits = Rom::new(&mut &include_bytes!("roms/digits.ch8")[..], "digits.ch8".to_string()).unwrap();
+	assert!(disasm_round_trip(&digits).unwrap() == digits.bytes());
+}